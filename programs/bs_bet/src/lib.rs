@@ -2,6 +2,7 @@ use anchor_lang::{
     prelude::*,
     solana_program::{
         clock::Clock,
+        hash::hashv,
         // ed25519_program, // Not strictly needed for invoke if bypassed
         // instruction::Instruction as SolanaInstruction, // Not strictly needed for invoke if bypassed
         // program::invoke, // Not strictly needed for invoke if bypassed
@@ -13,10 +14,19 @@ use pyth_solana_receiver_sdk::price_update::{
     get_feed_id_from_hex,
     PriceUpdateV2,
 };
+use pyth_solana_receiver_sdk::PYTH_PUSH_ORACLE_ID;
 // MagicBlock SDK integration
 use ephemeral_rollups_sdk::anchor::{delegate, ephemeral};
 use ephemeral_rollups_sdk::cpi::DelegateConfig;
 use ephemeral_rollups_sdk::ephem::commit_and_undelegate_accounts;
+// Token-2022 outcome tokens for market rounds
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_2022::spl_token_2022::instruction::AuthorityType,
+    token_2022::spl_token_2022::{extension::ExtensionType, state::Mint as MintState},
+    token_interface::{self, Burn, CloseAccount, InitializeMint2, Mint, MintTo, NonTransferableMintInitialize, SetAuthority, TokenAccount, TokenInterface, TransferChecked},
+};
 
 declare_id!("3awHJrzJbNCCLcQNEdh5mcVfPZW55w5v7tQhDwkx7Hpt"); // YOUR PROGRAM ID
 
@@ -27,6 +37,37 @@ const STRING_LENGTH_PREFIX: usize = 4; // For String serialization
 const MAX_ASSET_NAME_LENGTH: usize = 20; // Max length for asset_name string in ActiveBet
 const DISCRIMINATOR_LENGTH: usize = 8; // Anchor's account discriminator
 const INITIAL_USER_POINTS: u64 = 1000;
+const OUR_PRICE_DECIMALS: i32 = 6; // All stored prices use 6 decimals
+const OUTCOME_TOKEN_DECIMALS: u8 = 0; // 1 outcome token == 1 point staked
+const RECEIPT_DECIMALS: u8 = 0; // Receipt NFTs: 0 decimals, supply of exactly 1
+const BADGE_DECIMALS: u8 = 0; // Achievement badges: non-transferable, supply of exactly 1
+pub const BET_HISTORY_CAPACITY: usize = 32; // Most recent closed bets kept in UserBetHistory
+pub const IDEMPOTENCY_LOG_CAPACITY: usize = 16; // Most recent open_bet idempotency keys remembered per user
+pub const LEADERBOARD_CAPACITY: usize = 20; // Users ranked on each Leaderboard
+pub const MAX_BATCH_RESOLVE_BETS: usize = 20; // Keeps resolve_bets' return data under the 1024-byte limit
+pub const DEFAULT_VOID_GRACE_PERIOD_SECONDS: i64 = 24 * 3600; // Unresolved bets can be voided this long after expiry
+pub const DEFAULT_CANCEL_WINDOW_SECONDS: i64 = 30; // Fresh bets can be cancelled this long after opening
+pub const DEFAULT_CANCEL_MAX_PRICE_MOVE_BPS: u16 = 10; // ...as long as the price moved at most 0.10%
+pub const CANCEL_MAX_PRICE_AGE_SECONDS: u64 = 60; // Price used to check the move must be fresh
+const BPS_DENOMINATOR: u64 = 10_000;
+pub const MAX_LEVELS: usize = 10; // Length of ProgramConfig.level_xp_thresholds
+pub const XP_PER_BET: u64 = 10;   // Participation XP for every bet opened, on top of 1 XP per point staked
+pub const LIMIT_ORDER_MAX_PRICE_AGE_SECONDS: u64 = 60; // Keepers must prove the trigger with a fresh price
+pub const SCHEDULED_BET_MAX_PRICE_AGE_SECONDS: u64 = 60; // Strike of a scheduled bet must come from a fresh price
+pub const MARKET_ROUND_MAX_PRICE_AGE_SECONDS: u64 = 60; // Strike of a market round must come from a fresh price
+pub const DEFAULT_MARKET_ROUND_STAKING_SECONDS: i64 = 60; // Market rounds take stakes this long after the strike is set...
+pub const DEFAULT_MAX_MARKET_ROUND_DURATION_SECONDS: i64 = 24 * 3600; // ...and resolve at most this long after it
+pub const SCHEDULED_BET_ACTIVATION_WINDOW_SECONDS: i64 = 300; // Scheduled bets can only be activated this long after open_at
+pub const DEFAULT_REFILL_FLOOR_POINTS: u64 = 100; // claim_refill tops busted users back up to this balance...
+pub const DEFAULT_REFILL_COOLDOWN_SECONDS: i64 = 24 * 3600; // ...at most once per this period
+pub const DEFAULT_REFERRAL_BONUS_POINTS: u64 = 100; // Paid to both referrer and referee...
+pub const DEFAULT_REFERRAL_BONUS_BETS: u32 = 5;     // ...once the referee has this many won or lost bets
+pub const DEFAULT_REFERRAL_SHARE_BPS: u16 = 500;    // Referrer's share of the referee's winnings in the referral season
+pub const MIN_DISPLAY_NAME_LENGTH: usize = 3;
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 20; // Display names are [a-z0-9_] and double as the NameRegistry seed
+pub const MAX_AVATAR_URI_LENGTH: usize = 200;
+pub const MAX_BIO_LENGTH: usize = 160;
+pub const MAX_DISTRIBUTOR_CLAIMS: u32 = 8192; // Leaves per season distributor; keeps the claim bitmap at 1 KiB
 
 // --- Account Struct Definitions ---
 #[account]
@@ -37,8 +78,69 @@ pub struct UserAuthState {
     pub delegation_timestamp: i64,
     pub nonce: u64,         // For ensuring signed messages are unique (even if Ed25519 verify is demo-skipped)
     pub bump: u8,
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 8], // Zeroed space for future fields
+}
+pub const USER_AUTH_STATE_VERSION: u8 = 1;
+pub const USER_AUTH_STATE_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 1 + 8 + 8 + 1 + 1 + 64;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Direction {
+    #[default]
+    Down, // Serialized as 0, same as the old u8 encoding
+    Up,   // 1
+}
+
+impl Direction {
+    pub fn opposite(self) -> Self {
+        match self { Direction::Up => Direction::Down, Direction::Down => Direction::Up }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self { Direction::Up => "UP", Direction::Down => "DOWN" }
+    }
+}
+
+// Variant order matches the old u8 status values, so existing accounts deserialize unchanged
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BetStatus {
+    #[default]
+    Active,
+    Won,
+    Lost,
+    Push,
+    Voided,
+    Cancelled,
+    CashedOut,
+}
+
+// Variant order matches the old u8 values, so existing accounts deserialize unchanged
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum MarketRoundStatus {
+    #[default]
+    Open,
+    UpWon,
+    DownWon,
+    Flat, // No winning side: both outcome tokens redeem for their stake
+}
+
+// Variant order matches the old u8 values, so existing accounts deserialize unchanged
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum DirectionRule {
+    #[default]
+    Fixed,
+    FollowLastOutcome,
+    Alternate,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BetKind {
+    #[default]
+    Standard,   // open_bet
+    LimitOrder, // execute_limit_order
+    Scheduled,  // activate_scheduled_bet
+    Recurring,  // crank_recurring_bet
 }
-pub const USER_AUTH_STATE_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 1 + 8 + 8 + 1;
 
 #[account]
 #[derive(Default, Debug)]
@@ -47,12 +149,24 @@ pub struct ActiveBet {
     pub asset_name: String, // Max MAX_ASSET_NAME_LENGTH
     pub initial_price: u64,
     pub expiry_timestamp: i64,
-    pub direction: u8,      // 0 for DOWN, 1 for UP
+    pub direction: Direction,
     pub amount_staked: u64,
     pub resolved_price: u64,
-    pub status: u8,         // 0: Active, 1: Won, 2: Lost
+    pub status: BetStatus,
+    pub receipt_mint: Pubkey, // Pubkey::default() if no receipt NFT was minted for this bet
+    pub bet_index: u64,     // PDA seed: [b"bet", user, bet_index]
+    pub bump: u8,
+    pub rent_payer: Pubkey, // Receives the rent back when the settled bet is closed
+    pub opened_at: i64,
+    pub kind: BetKind,
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub season_id: u64,     // Season of the bettor's balance the stake came from
+    pub amount_credited: u64, // Points the settlement actually credited (payout or refund), to the bettor or receipt holder
+    pub recurring_bet: Pubkey, // Subscription still tracking this iteration, or default; blocks closing. Carved out of the reserved space
+    pub reserved: [u64; 2], // Zeroed space for future fields
 }
-pub const ACTIVE_BET_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 8 + 1 + 8 + 8 + 1;
+pub const ACTIVE_BET_VERSION: u8 = 1;
+pub const ACTIVE_BET_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 8 + 1 + 8 + 8 + 1 + 32 + 8 + 1 + 32 + 8 + 1 + 1 + 8 + 8 + 32 + 16;
 
 #[account]
 #[derive(Default, Debug)]
@@ -60,422 +174,3700 @@ pub struct UserProfile {
     pub authority: Pubkey,
     pub points: u64,
     pub bump: u8,
+    pub bet_count: u64, // Number of bets ever opened; the next bet is derived from [b"bet", authority, bet_count]
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub stats: BettingStats, // Carved out of the reserved space; zero for profiles created earlier
+    pub season_id: u64,     // Season the points balance belongs to; reset lazily when a new season starts
+    pub season_net_pnl: i64, // stats.net_pnl, counted for the current season only
+    // --- Version 2 ---
+    pub last_refill_at: i64, // Last claim_refill; 0 if the user never claimed one
+    pub referrer: Pubkey,    // Authority of the referring user, or default; carved out of the version 2 reserved space
+    // --- Version 3 ---
+    pub referral_season_id: u64,   // The referrer earns a share of winnings from bets in this season only
+    pub referral_winnings: u64,    // Winnings accrued towards the referrer's share, paid out by settle_referral
+    pub referral_bonus_paid: bool,
+    pub achievements: u64,         // Bitset of unlocked Achievements; carved out of the reserved space
+    pub xp: u64,                   // Earned by betting volume and participation; never spent. Carved out of the reserved space
+    pub reserved_points: u64,      // Current-season points held by open limit orders and scheduled bets. Carved out of the reserved space
+    pub reserved: [u64; 5],        // Zeroed space for future fields
 }
-pub const USER_PROFILE_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + 1;
+pub const USER_PROFILE_VERSION: u8 = 3;
+pub const USER_PROFILE_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + 1 + 8 + 1 + BETTING_STATS_SIZE + 8 + 8 + 8 + 32 + 8 + 8 + 1 + 64;
 
-// Helper function
-pub fn create_delegation_message(user_pubkey: &Pubkey, nonce: u64) -> String {
-    format!("BSBET_DELEGATE_AUTH:{}:{}", user_pubkey, nonce)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct BettingStats {
+    pub bets_placed: u32,
+    pub bets_won: u32,
+    pub bets_lost: u32,
+    pub current_win_streak: u16,
+    pub best_win_streak: u16,
+    pub total_staked: u64,
+    pub total_paid_out: u64,    // Payouts and refunds credited to this user for their own bets
+    pub net_pnl: i64,           // Settled bets only: total paid out minus their stakes
+    pub last_bet_timestamp: i64,
 }
+pub const BETTING_STATS_SIZE: usize = 4 + 4 + 4 + 2 + 2 + 8 + 8 + 8 + 8;
 
-// --- Program Module ---
-#[ephemeral]
-#[program]
-pub mod bs_bet {
-    use super::*;
+#[account]
+#[derive(Default, Debug)]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub resolver_tip_points: u64, // Tip paid to whoever resolves someone else's expired bet
+    pub keeper_fund_points: u64,  // Points available to pay resolver tips, topped up by the admin
+    pub bump: u8,
+    pub void_grace_period_seconds: i64, // After expiry + this, an unresolved bet can be voided and refunded
+    pub cancel_window_seconds: i64,     // A bet can be cancelled this long after opening...
+    pub cancel_max_price_move_bps: u16, // ...if the price has moved at most this much since opening
+    pub cancel_fee_bps: u16,            // Kept from the refund and added to the keeper fund
+    pub refill_floor_points: u64,       // claim_refill tops a balance below this back up to it; 0 disables refills
+    pub refill_cooldown_seconds: i64,   // Minimum time between two refills of the same profile
+    pub referral_bonus_points: u64,
+    pub referral_bonus_bets: u32,
+    pub referral_share_bps: u16,
+    pub level_xp_thresholds: [u64; MAX_LEVELS], // XP needed for levels 1..=MAX_LEVELS, ascending; trailing zeros are unused levels
+    pub max_stake_base: u64,          // Largest open_bet stake at level 0; 0 leaves stakes uncapped
+    pub max_stake_per_level: u64,     // Added to the stake cap for every level
+    pub refill_bonus_per_level: u64,  // Added to the refill floor for every level
+    pub market_round_staking_seconds: i64,      // Stakes on a market round close this long after it opens
+    pub max_market_round_duration_seconds: i64, // Longest duration initialize_market_round accepts
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const PROGRAM_CONFIG_VERSION: u8 = 1;
+pub const PROGRAM_CONFIG_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + 8 + 1 + 8 + 8 + 2 + 2 + 8 + 8 + 8 + 4 + 2 + 8 * MAX_LEVELS + 8 + 8 + 8 + 8 + 8 + 1 + 32;
 
-    pub fn create_user_profile(ctx: Context<CreateUserProfile>) -> Result<()> {
-        let user_profile = &mut ctx.accounts.user_profile;
-        user_profile.authority = *ctx.accounts.user_authority.key;
-        user_profile.points = INITIAL_USER_POINTS;
-        user_profile.bump = ctx.bumps.user_profile;
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct ConfigUpdateParams {
+    pub resolver_tip_points: Option<u64>,
+    pub void_grace_period_seconds: Option<i64>,
+    pub cancel_window_seconds: Option<i64>,
+    pub cancel_max_price_move_bps: Option<u16>,
+    pub cancel_fee_bps: Option<u16>,
+    pub refill_floor_points: Option<u64>,
+    pub refill_cooldown_seconds: Option<i64>,
+    pub referral_bonus_points: Option<u64>,
+    pub referral_bonus_bets: Option<u32>,
+    pub referral_share_bps: Option<u16>,
+    pub level_xp_thresholds: Option<[u64; MAX_LEVELS]>,
+    pub max_stake_base: Option<u64>,
+    pub max_stake_per_level: Option<u64>,
+    pub refill_bonus_per_level: Option<u64>,
+    pub market_round_staking_seconds: Option<i64>,
+    pub max_market_round_duration_seconds: Option<i64>,
+}
 
-        let auth_state = &mut ctx.accounts.user_auth_state_for_profile_creation;
-        if auth_state.user_authority == Pubkey::default() { // Initialize only if new
-            auth_state.user_authority = *ctx.accounts.user_authority.key;
-            auth_state.is_delegated = false; // Default to standard mode
-            auth_state.delegation_timestamp = 0;
-            auth_state.nonce = 0;
-            auth_state.bump = ctx.bumps.user_auth_state_for_profile_creation;
-        }
-        msg!("User profile created/updated. Auth state (is_delegated={}) initialized if new.", auth_state.is_delegated);
-        Ok(())
-    }
+#[account]
+#[derive(Default, Debug)]
+pub struct SeasonConfig {
+    pub current_season_id: u64,
+    pub season_started_at: i64,
+    pub season_ends_at: i64,          // end_season can be called by anyone from this time on
+    pub season_duration_seconds: i64,
+    pub starting_points: u64,         // Balance every profile is reset to when it joins a new season
+    pub bump: u8,
+}
+pub const SEASON_CONFIG_SPACE: usize = DISCRIMINATOR_LENGTH + 8 + 8 + 8 + 8 + 8 + 1;
 
-    pub fn manage_delegation(
-        ctx: Context<ManageDelegation>,
-        delegation_action: u8,
-        user_signed_message: Vec<u8>,
-        _signature: [u8; 64]) -> Result<()> {
-        let auth_state = &mut ctx.accounts.user_auth_state;
-        let user_key = ctx.accounts.user_authority.key();
-        let clock = Clock::get()?;
+// Created lazily by open_bet on the user's first bet of a season (or up front by join_season)
+#[account]
+#[derive(Default, Debug)]
+pub struct UserSeasonRecord {
+    pub user: Pubkey,
+    pub season_id: u64,
+    pub starting_points: u64,
+    pub joined_at: i64,
+    pub bets_placed: u32,
+    pub bump: u8,
+}
+pub const USER_SEASON_RECORD_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + 8 + 8 + 4 + 1;
 
-        if delegation_action == 1 { // User intends to delegate ("Enable Quick Bets")
-            if auth_state.user_authority == Pubkey::default() {
-                auth_state.user_authority = user_key;
-                auth_state.bump = ctx.bumps.user_auth_state;
-                auth_state.nonce = 0;
-            } else if auth_state.user_authority != user_key {
-                return Err(error!(BetError::UserProfileAuthorityMismatch));
-            }
-            // Client-side should ideally check if already delegated before calling.
-            // If called again while is_delegated = true, it effectively re-verifies for current nonce.
-            if auth_state.is_delegated && auth_state.nonce > 0 { // If already delegated and nonce was incremented
-                 msg!("Already processed for delegation. If MB SDK call failed, client can retry delegate_auth_state.");
-                 // No error, allow client to proceed to delegate_auth_state if needed.
-                 // Or, if nonce check is critical:
-                 // return Err(error!(BetError::AlreadyDelegated));
-            }
+// Final standings of a finished season, copied from the leaderboards by end_season
+#[account]
+#[derive(Default, Debug)]
+pub struct SeasonResult {
+    pub season_id: u64,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub points_standings: Vec<LeaderboardEntry>,
+    pub pnl_standings: Vec<LeaderboardEntry>,
+    pub bump: u8,
+}
+pub const SEASON_RESULT_SPACE: usize = DISCRIMINATOR_LENGTH + 8 + 8 + 8 + 2 * (4 + LEADERBOARD_CAPACITY * LEADERBOARD_ENTRY_SIZE) + 1;
 
+// Merkle distributor for a finished season's rewards. Each leaf is
+// hash(0x00 || index (u32 LE) || user_profile PDA || amount (u64 LE)); claimed leaves are set in the bitmap.
+#[account]
+#[derive(Default, Debug)]
+pub struct SeasonDistributor {
+    pub season_id: u64,
+    pub merkle_root: [u8; 32],
+    pub reward_mint: Pubkey,     // Paid out of the distributor's associated token account
+    pub num_claims: u32,
+    pub total_claimed: u64,
+    pub claimed_bitmap: Vec<u8>, // One bit per leaf index
+    pub bump: u8,
+}
+pub const fn season_distributor_space(num_claims: u32) -> usize {
+    DISCRIMINATOR_LENGTH + 8 + 32 + 32 + 4 + 8 + (4 + (num_claims as usize).div_ceil(8)) + 1
+}
 
-            let current_nonce = auth_state.nonce;
-            let expected_message = create_delegation_message(&user_key, current_nonce);
-            if user_signed_message != expected_message.as_bytes() {
-                msg!("Invalid signed message content. Expected for nonce {}.", current_nonce);
-                return Err(error!(BetError::InvalidDelegationSignature));
-            }
+#[account]
+#[derive(Default, Debug)]
+pub struct MarketRound {
+    pub round_id: u64,
+    pub asset_name: String, // Max MAX_ASSET_NAME_LENGTH
+    pub strike_price: u64,
+    pub start_timestamp: i64,
+    pub expiry_timestamp: i64,
+    pub resolved_price: u64,
+    pub status: MarketRoundStatus,
+    pub up_mint: Pubkey,
+    pub down_mint: Pubkey,
+    pub total_up_staked: u64,
+    pub total_down_staked: u64,
+    pub bump: u8,
+    pub season_id: u64,     // Stakes and redemptions only count in this season
+    pub lock_timestamp: i64, // Stakes are rejected from this time on
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const MARKET_ROUND_VERSION: u8 = 1;
+pub const MARKET_ROUND_SPACE: usize = DISCRIMINATOR_LENGTH + 8 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 8 + 8 + 8 + 1 + 32 + 32 + 8 + 8 + 1 + 8 + 8 + 1 + 32;
 
-            // --- ED25519 VERIFICATION SKIPPED (Hackathon Compromise) ---
-            msg!("DEMO MODE: Skipping on-chain Ed25519 signature verification for manage_delegation.");
-            // --- END OF SKIPPED VERIFICATION ---
+#[account]
+#[derive(Default, Debug)]
+pub struct ReceiptListing {
+    pub seller: Pubkey,
+    pub bet: Pubkey,
+    pub receipt_mint: Pubkey,
+    pub price_points: u64,
+    pub bump: u8,
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const RECEIPT_LISTING_VERSION: u8 = 1;
+pub const RECEIPT_LISTING_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 32 + 32 + 8 + 1 + 1 + 32;
 
-            auth_state.is_delegated = true; // Set to true: ready for MagicBlock SDK call
-            auth_state.delegation_timestamp = clock.unix_timestamp;
-            auth_state.nonce = auth_state.nonce.checked_add(1).ok_or(BetError::TimestampOverflow)?;
-            msg!("UserAuthState ready for MagicBlock SDK (is_delegated=true). Nonce incremented to {}.", auth_state.nonce);
+#[account]
+#[derive(Default, Debug)]
+pub struct LimitOrder {
+    pub user: Pubkey,
+    pub order_id: u64,
+    pub asset_name: String, // Max MAX_ASSET_NAME_LENGTH
+    pub trigger_price: u64,
+    pub trigger_above: bool, // true: execute when price >= trigger_price, false: when price <= trigger_price
+    pub direction: Direction,
+    pub amount_reserved: u64, // Stake already deducted from UserProfile.points
+    pub keeper_tip: u64,      // Also reserved; paid to the executing keeper's profile
+    pub duration_seconds: i64,
+    pub created_at: i64,
+    pub bump: u8,
+    pub season_id: u64,       // Season the reserve was taken from; refunds and the bet stay in it
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const LIMIT_ORDER_VERSION: u8 = 1;
+pub const LIMIT_ORDER_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 1 + 1 + 8 + 8 + 8 + 8 + 1 + 8 + 1 + 32;
 
-        } else if delegation_action == 0 { // User intends to undelegate ("Disable Quick Bets")
-        
-        // This action is called AFTER undelegate_from_magicblock has returned ownership.
-        if auth_state.user_authority != user_key {
-            return Err(error!(BetError::UserProfileAuthorityMismatch));
-        }
-        // No need to check !auth_state.is_delegated, we are explicitly setting it.
-        auth_state.is_delegated = false;
-        // Optionally reset nonce or timestamp here if desired after full undelegation.
-        // auth_state.nonce = 0; // Example
-        msg!("UserAuthState locally marked as not delegated (is_delegated=false).");
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LimitOrderParams {
+    pub order_id: u64,
+    pub asset_name: String,
+    pub trigger_price: u64,
+    pub trigger_above: bool,
+    pub direction: Direction,
+    pub amount: u64,
+    pub keeper_tip: u64,
+    pub duration_seconds: i64,
+}
+
+#[account]
+#[derive(Default, Debug)]
+pub struct ScheduledBet {
+    pub user: Pubkey,
+    pub schedule_id: u64,
+    pub asset_name: String, // Max MAX_ASSET_NAME_LENGTH
+    pub open_at: i64,       // Earliest activation time; the strike is the oracle price at activation
+    pub direction: Direction,
+    pub amount_reserved: u64, // Stake already deducted from UserProfile.points
+    pub duration_seconds: i64,
+    pub bump: u8,
+    pub season_id: u64,       // Season the reserve was taken from; refunds and the bet stay in it
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const SCHEDULED_BET_VERSION: u8 = 1;
+pub const SCHEDULED_BET_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 1 + 8 + 8 + 1 + 8 + 1 + 32;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ScheduledBetParams {
+    pub schedule_id: u64,
+    pub asset_name: String,
+    pub open_at: i64,
+    pub direction: Direction,
+    pub amount: u64,
+    pub duration_seconds: i64,
+}
+
+#[account]
+#[derive(Default, Debug)]
+pub struct RecurringBet {
+    pub user: Pubkey,
+    pub subscription_id: u64,
+    pub asset_name: String, // Max MAX_ASSET_NAME_LENGTH
+    pub stake_per_iteration: u64,
+    pub direction_rule: DirectionRule,
+    pub next_direction: Direction,
+    pub duration_seconds: i64,
+    pub max_iterations: u32,
+    pub iterations_opened: u32,
+    pub stop_loss: u64,       // Stop once cumulative losses reach this many points (0 = no stop-loss)
+    pub cumulative_pnl: i64,
+    pub current_bet: Pubkey,  // Pubkey::default() when no iteration is in flight
+    pub is_active: bool,
+    pub bump: u8,
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const RECURRING_BET_VERSION: u8 = 1;
+pub const RECURRING_BET_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + (STRING_LENGTH_PREFIX + MAX_ASSET_NAME_LENGTH) + 8 + 1 + 1 + 8 + 4 + 4 + 8 + 8 + 32 + 1 + 1 + 1 + 32;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct RecurringBetParams {
+    pub subscription_id: u64,
+    pub asset_name: String,
+    pub stake_per_iteration: u64,
+    pub direction_rule: DirectionRule,
+    pub initial_direction: Direction,
+    pub duration_seconds: i64,
+    pub max_iterations: u32,
+    pub stop_loss: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct BetSummary {
+    pub bet_index: u64,
+    pub direction: Direction,
+    pub status: BetStatus,
+    pub amount_staked: u64,
+    pub initial_price: u64,
+    pub resolved_price: u64,
+    pub expiry_timestamp: i64,
+}
+pub const BET_SUMMARY_SIZE: usize = 8 + 1 + 1 + 8 + 8 + 8 + 8;
+
+#[account]
+#[derive(Default, Debug)]
+pub struct UserBetHistory {
+    pub user: Pubkey,
+    pub entries: Vec<BetSummary>, // Ring buffer of at most BET_HISTORY_CAPACITY entries
+    pub next_slot: u16,           // Slot overwritten next once the buffer is full
+    pub total_archived: u64,
+    pub bump: u8,
+}
+pub const USER_BET_HISTORY_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + (4 + BET_HISTORY_CAPACITY * BET_SUMMARY_SIZE) + 2 + 8 + 1;
+
+impl UserBetHistory {
+    pub fn archive(&mut self, bet: &ActiveBet) -> Result<()> {
+        let summary = BetSummary {
+            bet_index: bet.bet_index,
+            direction: bet.direction,
+            status: bet.status,
+            amount_staked: bet.amount_staked,
+            initial_price: bet.initial_price,
+            resolved_price: bet.resolved_price,
+            expiry_timestamp: bet.expiry_timestamp,
+        };
+        if self.entries.len() < BET_HISTORY_CAPACITY {
+            self.entries.push(summary);
         } else {
-            return Err(error!(BetError::InvalidDelegationSignature)); // Or InvalidDelegationAction
+            self.entries[self.next_slot as usize] = summary;
         }
+        self.next_slot = ((self.next_slot as usize + 1) % BET_HISTORY_CAPACITY) as u16;
+        self.total_archived = self.total_archived.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
         Ok(())
     }
+}
 
-    pub fn delegate_auth_state(ctx: Context<DelegateAuthState>) -> Result<()> {
-        // The `#[delegate]` macro on the `DelegateAuthState` struct makes the `delegate_pda` method
-        // available on `ctx.accounts` (which is an instance of `DelegateAuthState`).
-        // The method uses the field marked with `#[account(del)]` (i.e., `ctx.accounts.pda`)
-        // as the target account for delegation.
-    
-        // The seeds passed here should be the seeds used to derive the PDA that
-        // `ctx.accounts.pda` (the AccountInfo) points to.
-        // Your UserAuthState PDA is derived with seeds: [b"auth_state", payer.key()]
-        msg!("Attempting to delegate PDA: {} with payer: {}", ctx.accounts.pda.key(), ctx.accounts.payer.key());
-        ctx.accounts.delegate_pda( // Calling the method on ctx.accounts
-            // First argument is typically the fee payer for the delegation transaction,
-            // but delegate_pda might just need it to confirm authority or for seeds.
-            // In the MB example, ctx.accounts.payer (Signer from DelegateInput) was used.
-            &ctx.accounts.payer,
-            // Seeds for the PDA being delegated (UserAuthState)
-            // These are the seeds used to *find* or *verify* the `ctx.accounts.pda`.
-            // The `pda` field itself is already resolved to a PublicKey.
-            // MagicBlock's example just passed `&[TEST_PDA_SEED]`.
-            // Your seeds are `b"auth_state"` and `payer.key()`.
-            &[
-                b"auth_state".as_ref(),
-                ctx.accounts.payer.key().as_ref() // payer.key() IS the second seed for your UserAuthState PDA
-            ],
-            DelegateConfig::default(),
-        )?;
-    
-        msg!("UserAuthState PDA ({}) successfully delegated via MagicBlock SDK by payer: {}", ctx.accounts.pda.key(), ctx.accounts.payer.key());
-        Ok(())
-    }
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct IdempotencyEntry {
+    pub key: [u8; 16],
+    pub bet_index: u64, // Bet opened with this key
+}
+pub const IDEMPOTENCY_ENTRY_SIZE: usize = 16 + 8;
 
-    pub fn open_bet(
-        ctx: Context<OpenBetAccounts>,
-        asset_name_arg: String,
-        direction_arg: u8,
-        amount_arg: u64,
-        duration_seconds_arg: i64,
-        user_authority_for_pdas: Pubkey ) -> Result<()> {
-        let auth_state = &mut ctx.accounts.user_auth_state;
+#[account]
+#[derive(Default, Debug)]
+pub struct IdempotencyLog {
+    pub user: Pubkey,
+    pub entries: Vec<IdempotencyEntry>, // Ring buffer of at most IDEMPOTENCY_LOG_CAPACITY entries
+    pub next_slot: u16,
+    pub bump: u8,
+    pub version: u8,        // 0 for accounts created before versioning; see migrate_account
+    pub reserved: [u64; 4], // Zeroed space for future fields
+}
+pub const IDEMPOTENCY_LOG_VERSION: u8 = 1;
+pub const IDEMPOTENCY_LOG_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + (4 + IDEMPOTENCY_LOG_CAPACITY * IDEMPOTENCY_ENTRY_SIZE) + 2 + 1 + 1 + 32;
 
-        // Initialize UserAuthState if it's new
-        if auth_state.user_authority == Pubkey::default() {
-            auth_state.user_authority = user_authority_for_pdas;
-            auth_state.is_delegated = false; // Default to false for new users
-            auth_state.delegation_timestamp = 0;
-            auth_state.nonce = 0;
-            auth_state.bump = ctx.bumps.user_auth_state;
-        } else if auth_state.user_authority != user_authority_for_pdas {
-            // This ensures the PDA being used matches the intended user
-            return Err(error!(BetError::UserProfileAuthorityMismatch));
-        }
+impl IdempotencyLog {
+    pub fn find(&self, key: &[u8; 16]) -> Option<u64> {
+        self.entries.iter().find(|entry| &entry.key == key).map(|entry| entry.bet_index)
+    }
 
-        // This signer check is important for standard (non-MagicBlock-delegated) bets
-        if !auth_state.is_delegated && ctx.accounts.user_signer.key() != user_authority_for_pdas {
-            return Err(error!(BetError::UserProfileAuthorityMismatch));
+    pub fn record(&mut self, key: [u8; 16], bet_index: u64) {
+        let entry = IdempotencyEntry { key, bet_index };
+        if self.entries.len() < IDEMPOTENCY_LOG_CAPACITY {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next_slot as usize] = entry;
         }
+        self.next_slot = ((self.next_slot as usize + 1) % IDEMPOTENCY_LOG_CAPACITY) as u16;
+    }
+}
 
-        let user_profile = &mut ctx.accounts.user_profile;
-        // Initialize UserProfile if it's new
-        if user_profile.authority == Pubkey::default() {
-            user_profile.authority = user_authority_for_pdas;
-            user_profile.points = INITIAL_USER_POINTS;
-            user_profile.bump = ctx.bumps.user_profile;
-        } else if user_profile.authority != user_authority_for_pdas {
-            // This ensures the PDA being used matches the intended user
-            return Err(error!(BetError::UserProfileAuthorityMismatch));
-        }
+// Bit positions in UserProfile.achievements; append new achievements at the end
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Achievement {
+    FirstBet,
+    FirstWin,
+    WinStreak10,
+    Bets1000,
+}
 
-        // --- Rest of your open_bet logic: validation, points, Pyth, set bet_account fields ---
-        // This part was generally correct in your versions.
-        let bet_account = &mut ctx.accounts.bet_account;
-        let clock = Clock::get()?;
-        let price_update_account = &ctx.accounts.pyth_price_feed;
+impl Achievement {
+    pub const ALL: [Achievement; 4] = [Achievement::FirstBet, Achievement::FirstWin, Achievement::WinStreak10, Achievement::Bets1000];
 
-        // Validations
-        if asset_name_arg != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
-        if direction_arg != 0 && direction_arg != 1 { return Err(error!(BetError::InvalidDirection)); }
-        if amount_arg == 0 { return Err(error!(BetError::ZeroAmount)); }
-        if duration_seconds_arg <= 0 { return Err(error!(BetError::InvalidDuration)); }
-        if user_profile.points < amount_arg { return Err(error!(BetError::InsufficientPoints)); }
+    pub fn bit(self) -> u64 {
+        1 << (self as u8)
+    }
 
-        // Deduct points
-        user_profile.points = user_profile.points.checked_sub(amount_arg).ok_or_else(|| error!(BetError::InsufficientPoints))?;
-        msg!("User {} points: {} -> {}", user_authority_for_pdas, user_profile.points + amount_arg, user_profile.points);
+    pub fn is_earned(self, stats: &BettingStats) -> bool {
+        match self {
+            Achievement::FirstBet => stats.bets_placed >= 1,
+            Achievement::FirstWin => stats.bets_won >= 1,
+            Achievement::WinStreak10 => stats.best_win_streak >= 10,
+            Achievement::Bets1000 => stats.bets_placed >= 1000,
+        }
+    }
+}
 
-        // Pyth Price & Adjustment (your detailed logic)
-        let target_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID_HEX).map_err(|_| BetError::InvalidPythFeedIdFormat)?;
-        let current_pyth_price_struct = price_update_account.get_price_no_older_than(&clock, MAXIMUM_PRICE_AGE_SECONDS, &target_feed_id)
-            .map_err(|e| { msg!("Pyth err in open_bet: {:?}", e); BetError::PythPriceFeedError })?;
-        let pyth_price_value = current_pyth_price_struct.price;
-        let pyth_exponent = current_pyth_price_struct.exponent;
-        if pyth_price_value < 0 { return Err(error!(BetError::NegativePythPrice)); }
-        let mut adjusted_price = pyth_price_value as u64;
-        let our_price_decimals: i32 = 6;
-        if pyth_exponent < 0 { let se = our_price_decimals - pyth_exponent.abs(); if se < 0 { for _ in 0..se.abs() { adjusted_price /= 10; }} else if se > 0 { for _ in 0..se { adjusted_price = adjusted_price.checked_mul(10).ok_or(BetError::PriceCalculationOverflow)?;}}} else if pyth_exponent == 0 && our_price_decimals > 0 { for _ in 0..our_price_decimals { adjusted_price = adjusted_price.checked_mul(10).ok_or(BetError::PriceCalculationOverflow)?;}} else if pyth_exponent > 0 { if our_price_decimals > pyth_exponent { let de = our_price_decimals - pyth_exponent; for _ in 0..de { adjusted_price = adjusted_price.checked_mul(10).ok_or(BetError::PriceCalculationOverflow)?;}} else if pyth_exponent > our_price_decimals { let de = pyth_exponent - our_price_decimals; for _ in 0..de { adjusted_price /= 10;}}}
-        
-        msg!("Calculated initial price for bet: {}", adjusted_price); // Log the price
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LeaderboardMetric {
+    #[default]
+    Points,
+    NetPnl,
+}
 
-        // Set bet_account fields
-        bet_account.user = user_authority_for_pdas;
-        bet_account.asset_name = asset_name_arg; // Use the argument
-        bet_account.initial_price = adjusted_price;
-        bet_account.expiry_timestamp = clock.unix_timestamp.checked_add(duration_seconds_arg).ok_or(BetError::TimestampOverflow)?;
-        bet_account.direction = direction_arg;
-        bet_account.amount_staked = amount_arg;
-        bet_account.resolved_price = 0;
-        bet_account.status = 0; // Active
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct LeaderboardEntry {
+    pub user: Pubkey,
+    pub score: i64,
+    pub updated_at: i64, // When the user reached this score; earlier wins ties
+}
+pub const LEADERBOARD_ENTRY_SIZE: usize = 32 + 8 + 8;
 
-        msg!("Bet opened. UserAuthState.is_delegated: {}", auth_state.is_delegated);
-        Ok(())
+// One board per metric: PDA [b"leaderboard", metric as u8]
+#[account]
+#[derive(Default, Debug)]
+pub struct Leaderboard {
+    pub metric: LeaderboardMetric,
+    pub season_id: u64,
+    pub entries: Vec<LeaderboardEntry>, // Best first, at most LEADERBOARD_CAPACITY
+    pub bump: u8,
+}
+pub const LEADERBOARD_SPACE: usize = DISCRIMINATOR_LENGTH + 1 + 8 + (4 + LEADERBOARD_CAPACITY * LEADERBOARD_ENTRY_SIZE) + 1;
+
+// Public-facing profile details, kept next to the UserProfile at [b"profile_metadata", authority]
+#[account]
+#[derive(Default, Debug)]
+pub struct ProfileMetadata {
+    pub authority: Pubkey,
+    pub display_name: String, // Empty if unset; otherwise owned through its NameRegistry
+    pub avatar_uri: String,
+    pub bio: String,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+pub const PROFILE_METADATA_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + (STRING_LENGTH_PREFIX + MAX_DISPLAY_NAME_LENGTH) + (STRING_LENGTH_PREFIX + MAX_AVATAR_URI_LENGTH) + (STRING_LENGTH_PREFIX + MAX_BIO_LENGTH) + 8 + 1;
+
+// Existence of [b"name_registry", display_name] reserves the name for its owner
+#[account]
+#[derive(Default, Debug)]
+pub struct NameRegistry {
+    pub owner: Pubkey,
+    pub bump: u8,
+}
+pub const NAME_REGISTRY_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 1;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct ProfileUpdateParams {
+    pub display_name: Option<String>, // Some("") releases the current name
+    pub avatar_uri: Option<String>,
+    pub bio: Option<String>,
+}
+
+pub fn validate_display_name(name: &str) -> Result<()> {
+    let valid_length = (MIN_DISPLAY_NAME_LENGTH..=MAX_DISPLAY_NAME_LENGTH).contains(&name.len());
+    let valid_charset = name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    if !valid_length || !valid_charset { return Err(error!(BetError::InvalidDisplayName)); }
+    Ok(())
+}
+
+pub fn validate_avatar_uri(uri: &str) -> Result<()> {
+    if uri.is_empty() { return Ok(()); }
+    let valid_scheme = ["https://", "ipfs://", "ar://"].iter().any(|scheme| uri.starts_with(scheme));
+    let valid_charset = uri.bytes().all(|b| b.is_ascii_graphic());
+    if uri.len() > MAX_AVATAR_URI_LENGTH || !valid_scheme || !valid_charset { return Err(error!(BetError::InvalidAvatarUri)); }
+    Ok(())
+}
+
+pub fn validate_bio(bio: &str) -> Result<()> {
+    if bio.len() > MAX_BIO_LENGTH || bio.chars().any(char::is_control) { return Err(error!(BetError::InvalidBio)); }
+    Ok(())
+}
+
+impl SeasonDistributor {
+    pub fn is_claimed(&self, index: u32) -> bool {
+        self.claimed_bitmap[(index / 8) as usize] & (1 << (index % 8)) != 0
     }
 
-    pub fn resolve_bet(ctx: Context<ResolveBetAccounts>) -> Result<()> {
-        // --- (Your existing resolve_bet logic, ensure Pyth price adjustment is complete) ---
-        let bet_account = &mut ctx.accounts.bet_account;
-        let user_profile = &mut ctx.accounts.user_profile;
-        let clock = &ctx.accounts.clock;
-        let price_update_account = &ctx.accounts.pyth_price_feed;
-        let auth_state = &ctx.accounts.user_auth_state; // For logging
+    pub fn set_claimed(&mut self, index: u32) {
+        self.claimed_bitmap[(index / 8) as usize] |= 1 << (index % 8);
+    }
+}
 
-        if bet_account.status != 0 { return Err(error!(BetError::BetNotActiveOrAlreadyResolved));}
-        if clock.unix_timestamp <= bet_account.expiry_timestamp { return Err(error!(BetError::BetNotYetExpired));}
-        // PDA authority checks are handled by constraints on UserProfile and UserAuthState in ResolveBetAccounts
+impl Leaderboard {
+    pub fn score_of(&self, profile: &UserProfile) -> i64 {
+        match self.metric {
+            LeaderboardMetric::Points => i64::try_from(profile.points).unwrap_or(i64::MAX),
+            LeaderboardMetric::NetPnl => profile.season_net_pnl,
+        }
+    }
 
-        let target_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID_HEX).map_err(|_| BetError::InvalidPythFeedIdFormat)?;
-        let resolved_pyth_price_struct = price_update_account.get_price_no_older_than(clock, MAXIMUM_PRICE_AGE_SECONDS, &target_feed_id).map_err(|e| {msg!("Pyth error: {:?}", e); BetError::PythPriceFeedError})?;
-        let pyth_resolved_price_value = resolved_pyth_price_struct.price;
-        let pyth_resolved_exponent = resolved_pyth_price_struct.exponent;
-        if pyth_resolved_price_value < 0 { return Err(error!(BetError::NegativePythPrice));}
-        let mut adjusted_resolved_price = pyth_resolved_price_value as u64;
-        let our_price_decimals: i32 = 6;
-        if pyth_resolved_exponent < 0 { let scaling_factor_exponent = our_price_decimals - pyth_resolved_exponent.abs(); if scaling_factor_exponent < 0 { for _ in 0..scaling_factor_exponent.abs() { adjusted_resolved_price /= 10; }} else if scaling_factor_exponent > 0 { for _ in 0..scaling_factor_exponent { adjusted_resolved_price = adjusted_resolved_price.checked_mul(10).ok_or_else(|| error!(BetError::PriceCalculationOverflow))?;}}} else if pyth_resolved_exponent == 0 && our_price_decimals > 0 { for _ in 0..our_price_decimals { adjusted_resolved_price = adjusted_resolved_price.checked_mul(10).ok_or_else(|| error!(BetError::PriceCalculationOverflow))?;}} else if pyth_resolved_exponent > 0 { if our_price_decimals > pyth_resolved_exponent { let diff_expo = our_price_decimals - pyth_resolved_exponent; for _ in 0..diff_expo { adjusted_resolved_price = adjusted_resolved_price.checked_mul(10).ok_or_else(|| error!(BetError::PriceCalculationOverflow))?;}} else if pyth_resolved_exponent > our_price_decimals { let diff_expo = pyth_resolved_exponent - our_price_decimals; for _ in 0..diff_expo { adjusted_resolved_price /= 10;}}}
-        bet_account.resolved_price = adjusted_resolved_price;
-        msg!("Resolved price: {}", adjusted_resolved_price);
-        let won = if bet_account.direction == 1 { bet_account.resolved_price > bet_account.initial_price } else { bet_account.resolved_price < bet_account.initial_price };
-        if won {
-            bet_account.status = 1; // Won
-            let payout_amount = bet_account.amount_staked.checked_mul(2).ok_or(BetError::PriceCalculationOverflow)?;
-            user_profile.points = user_profile.points.checked_add(payout_amount).ok_or(BetError::PriceCalculationOverflow)?;
-            msg!("Bet WON! Payout: {}. New points: {}", payout_amount, user_profile.points);
+    // Higher score first; ties go to whoever reached the score first, then to the lower pubkey.
+    fn rank_order(a: &LeaderboardEntry, b: &LeaderboardEntry) -> std::cmp::Ordering {
+        b.score.cmp(&a.score)
+            .then(a.updated_at.cmp(&b.updated_at))
+            .then(a.user.to_bytes().cmp(&b.user.to_bytes()))
+    }
+
+    // Re-ranks a listed user, or inserts a new one if they beat the current cutoff.
+    // Returns whether the board changed.
+    pub fn submit(&mut self, user: Pubkey, score: i64, timestamp: i64) -> bool {
+        let entry = LeaderboardEntry { user, score, updated_at: timestamp };
+        if let Some(position) = self.entries.iter().position(|listed| listed.user == user) {
+            if self.entries[position].score == score { return false; }
+            self.entries[position] = entry;
         } else {
-            bet_account.status = 2; // Lost
-            msg!("Bet LOST. Points: {}", user_profile.points);
+            if self.entries.len() >= LEADERBOARD_CAPACITY {
+                match self.entries.last() {
+                    Some(cutoff) if Self::rank_order(&entry, cutoff).is_lt() => {}
+                    _ => return false,
+                }
+                self.entries.pop();
+            }
+            self.entries.push(entry);
         }
-        msg!("Bet resolved. User: {}. Mode: {}.", bet_account.user, if auth_state.is_delegated {"Quick"} else {"Standard"});
-        Ok(())
+        self.entries.sort_by(Self::rank_order);
+        true
     }
 
-    pub fn undelegate_from_magicblock(ctx: Context<UndelegateFromMagicBlock>) -> Result<()> {
-        msg!("Starting undelegation from MagicBlock...");
-    
-        // User authority must match the authority record IF we could read it.
-        // For now, we trust the client passed the correct user_auth_state_pda for their user.
-        // A more robust check would involve deriving the PDA here using ctx.accounts.user_authority.key()
-        // and ensuring it matches ctx.accounts.user_auth_state_to_undelegate.key().
-    
-        commit_and_undelegate_accounts(
-            &ctx.accounts.user_authority.to_account_info(), // Payer
-            vec![&ctx.accounts.user_auth_state_to_undelegate], // The AccountInfo of UserAuthState PDA
-            &ctx.accounts.magic_context.to_account_info(),
-            &ctx.accounts.magic_program.to_account_info(),
+    // Submits the current score of every profile a settlement touched.
+    pub fn rerank(&mut self, profiles: &[&UserProfile], timestamp: i64) {
+        for profile in profiles {
+            if profile.season_id != self.season_id { continue; } // Not yet moved into the board's season
+            let score = self.score_of(profile);
+            if self.submit(profile.authority, score, timestamp) {
+                msg!("Leaderboard {:?}: {} now at {}", self.metric, profile.authority, score);
+            }
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BatchResolveOutcome {
+    Won,
+    Lost,
+    SkippedNotActive,
+    SkippedNotExpired,
+    SkippedProfileMismatch,
+    SkippedReceiptBet, // Needs the receipt holder's accounts: use resolve_bet
+    Push,              // Price unchanged: the stake was refunded
+    SkippedInvalidAccount, // Not a writable bet and user profile of this program (e.g. the bet was closed)
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct BetResolutionResult {
+    pub bet: Pubkey,
+    pub outcome: BatchResolveOutcome,
+    pub payout: u64,
+}
+
+// Helper function
+pub fn create_delegation_message(user_pubkey: &Pubkey, nonce: u64) -> String {
+    format!("BSBET_DELEGATE_AUTH:{}:{}", user_pubkey, nonce)
+}
+
+// Scales a raw Pyth price/exponent pair to our OUR_PRICE_DECIMALS fixed-point representation.
+pub fn adjust_pyth_price(pyth_price_value: i64, pyth_exponent: i32) -> Result<u64> {
+    if pyth_price_value < 0 { return Err(error!(BetError::NegativePythPrice)); }
+    let mut adjusted_price = pyth_price_value as u64;
+    let scaling_exponent = OUR_PRICE_DECIMALS + pyth_exponent;
+    if scaling_exponent < 0 {
+        for _ in 0..scaling_exponent.abs() { adjusted_price /= 10; }
+    } else {
+        for _ in 0..scaling_exponent { adjusted_price = adjusted_price.checked_mul(10).ok_or(BetError::PriceCalculationOverflow)?; }
+    }
+    Ok(adjusted_price)
+}
+
+// Reads the SOL/USD price from a Pyth update (rejecting stale prices) and adjusts it to our decimals.
+pub fn get_adjusted_sol_usd_price(price_update_account: &PriceUpdateV2, clock: &Clock) -> Result<u64> {
+    Ok(read_sol_usd_price(price_update_account, clock, MAXIMUM_PRICE_AGE_SECONDS)?.0)
+}
+
+// Same as above with a caller-chosen maximum age. Returns (adjusted price, publish time).
+pub fn read_sol_usd_price(price_update_account: &PriceUpdateV2, clock: &Clock, maximum_age_seconds: u64) -> Result<(u64, i64)> {
+    let target_feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID_HEX).map_err(|_| BetError::InvalidPythFeedIdFormat)?;
+    let pyth_price_struct = price_update_account.get_price_no_older_than(clock, maximum_age_seconds, &target_feed_id)
+        .map_err(|e| { msg!("Pyth error: {:?}", e); BetError::PythPriceFeedError })?;
+    Ok((adjust_pyth_price(pyth_price_struct.price, pyth_price_struct.exponent)?, pyth_price_struct.publish_time))
+}
+
+// The sponsored SOL/USD feed kept fresh by Pyth's push oracle (shard 0). Unlike a price update anyone can
+// post, it is a single canonical account, so its staleness shows the oracle itself has stopped publishing.
+pub fn sol_usd_push_feed_address() -> Result<Pubkey> {
+    let feed_id = get_feed_id_from_hex(SOL_USD_FEED_ID_HEX).map_err(|_| BetError::InvalidPythFeedIdFormat)?;
+    Ok(Pubkey::find_program_address(&[0u16.to_le_bytes().as_ref(), feed_id.as_ref()], &PYTH_PUSH_ORACLE_ID).0)
+}
+
+// Decides who is paid for a receipt bet's payout or refund. A bettor who still holds their own receipt is
+// paid through user_profile and must not pass a holder profile; anyone else needs receipt_holder_profile.
+pub fn receipt_pays_holder(bet: &ActiveBet, receipt_token_account: Option<&InterfaceAccount<TokenAccount>>, holder_profile: Option<&Account<UserProfile>>) -> Result<bool> {
+    if bet.receipt_mint == Pubkey::default() { return Ok(false); }
+    let receipt_token_account = receipt_token_account.ok_or(BetError::ReceiptAccountsMissing)?;
+    if receipt_token_account.mint != bet.receipt_mint || receipt_token_account.amount != 1 { return Err(error!(BetError::InvalidReceiptTokenAccount)); }
+    if receipt_token_account.owner == bet.user {
+        if holder_profile.is_some() { return Err(error!(BetError::DuplicateProfileAccount)); }
+        return Ok(false);
+    }
+    let holder_profile = holder_profile.ok_or(BetError::ReceiptAccountsMissing)?;
+    if holder_profile.authority != receipt_token_account.owner { return Err(error!(BetError::ReceiptHolderProfileMismatch)); }
+    Ok(true)
+}
+
+// Rejects the bet if the oracle price drifted more than max_deviation_bps from what the user saw.
+pub fn check_price_slippage(oracle_price: u64, expected_price: Option<u64>, max_deviation_bps: u16) -> Result<()> {
+    let Some(expected_price) = expected_price else { return Ok(()); };
+    let deviation = (oracle_price.abs_diff(expected_price) as u128) * (BPS_DENOMINATOR as u128);
+    if deviation > (expected_price as u128) * (max_deviation_bps as u128) {
+        msg!("Slippage exceeded: oracle {}, expected {}, max {} bps", oracle_price, expected_price, max_deviation_bps);
+        return Err(error!(BetError::SlippageExceeded));
+    }
+    Ok(())
+}
+
+// Seasons are enforced once initialize_seasons has created the season_config PDA. Callers pin the
+// address with seeds; before that the account is an empty system account and this returns None.
+pub fn load_season_config(season_config_info: &AccountInfo) -> Result<Option<SeasonConfig>> {
+    if season_config_info.owner != &crate::ID || season_config_info.data_is_empty() { return Ok(None); }
+    let data = season_config_info.try_borrow_data()?;
+    Ok(Some(SeasonConfig::try_deserialize(&mut &data[..])?))
+}
+
+// Creates a program-owned PDA. A plain create_account fails if anyone has already sent lamports to the
+// address, so a pre-funded account is topped up to rent exemption, then allocated and assigned instead.
+pub fn create_pda_account<'info>(payer: &AccountInfo<'info>, new_account: &AccountInfo<'info>, system_program: &AccountInfo<'info>, space: usize, owner: &Pubkey, signer_seeds: &[&[&[u8]]]) -> Result<()> {
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+    let current_lamports = new_account.lamports();
+    if current_lamports == 0 {
+        return anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount { from: payer.clone(), to: new_account.clone() },
+                signer_seeds,
+            ),
+            rent_lamports,
+            space as u64,
+            owner,
+        );
+    }
+    if current_lamports < rent_lamports {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer { from: payer.clone(), to: new_account.clone() },
+            ),
+            rent_lamports - current_lamports,
         )?;
-    
-        // After this SDK call, ownership of user_auth_state_to_undelegate (the PDA)
-        // should be transferred BACK to this bs_bet program.
-        // Now, we need to reload it as Account<UserAuthState> to modify its data.
-        // This requires a CPI to our own program or a separate instruction.
-        // OR, the SDK itself might allow updating data during undelegation (unlikely).
-    
-        // For simplicity in the demo, we assume commit_and_undelegate_accounts ALSO
-        // allows us to update our internal flag, OR the client just knows.
-        // The most important thing is that MagicBlock no longer considers it delegated.
-        // To update is_delegated = false, we need another instruction AFTER ownership is back.
-    
-        // For the demo: this instruction primarily triggers MB undelegation.
-        // The client will then re-fetch UserAuthState (expecting ownership back)
-        // and then call manage_delegation(0,...) to set is_delegated=false. This is clunky.
-    
-        // Ideal: commit_and_undelegate_accounts SDK returns ownership, THEN we set is_delegated=false.
-        // If the SDK doesn't allow immediate modification after ownership transfer within the same IX,
-        // we need two steps on client:
-        // 1. Call this `undelegate_from_magicblock`.
-        // 2. Client then calls `manage_delegation(0, ...)` which can now access UserAuthState as Account<T>
-        //    and set is_delegated = false.
-    
-        msg!("MagicBlock SDK undelegation called. Ownership should be returned to this program.");
-        msg!("Client should now call manage_delegation(action=0) to finalize local state.");
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate { account_to_allocate: new_account.clone() },
+            signer_seeds,
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign { account_to_assign: new_account.clone() },
+            signer_seeds,
+        ),
+        owner,
+    )
+}
+
+// Accounts whose layout only ever grows at the end: an older account, zero-padded to the current
+// size, deserializes as the current struct with version 0 (or whatever version it was written with).
+pub trait VersionedAccount: AccountSerialize + AccountDeserialize + Discriminator {
+    const VERSION: u8;
+    const SPACE: usize;
+    fn version_mut(&mut self) -> &mut u8;
+    // Fills in fields the old version had no equivalent for; zero is not always a usable default.
+    fn migrate_from(&mut self, _old_version: u8) {}
+}
+
+pub fn season_reward_leaf(index: u32, user_profile: &Pubkey, amount: u64) -> [u8; 32] {
+    hashv(&[&[0u8], &index.to_le_bytes(), user_profile.as_ref(), &amount.to_le_bytes()]).to_bytes()
+}
+
+// Sorted-pair merkle proof; inner nodes are domain-separated from leaves with a 0x01 prefix.
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            hashv(&[&[1u8], &node, sibling]).to_bytes()
+        } else {
+            hashv(&[&[1u8], sibling, &node]).to_bytes()
+        }
+    });
+    computed == *root
+}
+
+impl VersionedAccount for UserProfile {
+    const VERSION: u8 = USER_PROFILE_VERSION;
+    const SPACE: usize = USER_PROFILE_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for UserAuthState {
+    const VERSION: u8 = USER_AUTH_STATE_VERSION;
+    const SPACE: usize = USER_AUTH_STATE_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for ActiveBet {
+    const VERSION: u8 = ACTIVE_BET_VERSION;
+    const SPACE: usize = ACTIVE_BET_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+    fn migrate_from(&mut self, old_version: u8) {
+        // Version 0 bets were always paid for by the bettor, and close_bet refunds the rent payer
+        if old_version == 0 && self.rent_payer == Pubkey::default() { self.rent_payer = self.user; }
+    }
+}
+
+impl VersionedAccount for ProgramConfig {
+    const VERSION: u8 = PROGRAM_CONFIG_VERSION;
+    const SPACE: usize = PROGRAM_CONFIG_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+    fn migrate_from(&mut self, old_version: u8) {
+        // A zero staking window would close every new market round as soon as it opens
+        if old_version == 0 && self.market_round_staking_seconds == 0 {
+            self.market_round_staking_seconds = DEFAULT_MARKET_ROUND_STAKING_SECONDS;
+            self.max_market_round_duration_seconds = DEFAULT_MAX_MARKET_ROUND_DURATION_SECONDS;
+        }
+    }
+}
+
+impl VersionedAccount for MarketRound {
+    const VERSION: u8 = MARKET_ROUND_VERSION;
+    const SPACE: usize = MARKET_ROUND_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for ReceiptListing {
+    const VERSION: u8 = RECEIPT_LISTING_VERSION;
+    const SPACE: usize = RECEIPT_LISTING_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for LimitOrder {
+    const VERSION: u8 = LIMIT_ORDER_VERSION;
+    const SPACE: usize = LIMIT_ORDER_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for ScheduledBet {
+    const VERSION: u8 = SCHEDULED_BET_VERSION;
+    const SPACE: usize = SCHEDULED_BET_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for RecurringBet {
+    const VERSION: u8 = RECURRING_BET_VERSION;
+    const SPACE: usize = RECURRING_BET_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+impl VersionedAccount for IdempotencyLog {
+    const VERSION: u8 = IDEMPOTENCY_LOG_VERSION;
+    const SPACE: usize = IDEMPOTENCY_LOG_SPACE;
+    fn version_mut(&mut self) -> &mut u8 { &mut self.version }
+}
+
+// Deserializes an account written with an older, shorter layout; fields it predates read as zero.
+pub fn deserialize_padded<T: VersionedAccount>(data: &[u8]) -> Result<T> {
+    let mut padded_data = data.to_vec();
+    if padded_data.len() < T::SPACE { padded_data.resize(T::SPACE, 0); }
+    T::try_deserialize(&mut padded_data.as_slice())
+}
+
+// Reallocs the account to T::SPACE (payer tops up rent) and rewrites it with the current version.
+// Returns the version the account had before.
+pub fn upgrade_account_layout<'info, T: VersionedAccount>(account_info: &AccountInfo<'info>, payer: &AccountInfo<'info>, system_program: &AccountInfo<'info>) -> Result<u8> {
+    let mut account = deserialize_padded::<T>(&account_info.try_borrow_data()?)?;
+    let old_version = *account.version_mut();
+    if old_version > T::VERSION { return Err(error!(BetError::UnsupportedAccountVersion)); }
+    if old_version == T::VERSION && account_info.data_len() >= T::SPACE { return Ok(old_version); }
+
+    if account_info.data_len() < T::SPACE {
+        let rent_top_up = Rent::get()?.minimum_balance(T::SPACE).saturating_sub(account_info.lamports());
+        if rent_top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    anchor_lang::system_program::Transfer { from: payer.clone(), to: account_info.clone() },
+                ),
+                rent_top_up,
+            )?;
+        }
+        account_info.realloc(T::SPACE, true)?;
+    }
+    account.migrate_from(old_version);
+    *account.version_mut() = T::VERSION;
+    let mut data = account_info.try_borrow_mut_data()?;
+    data.fill(0);
+    account.try_serialize(&mut &mut data[..])?;
+    Ok(old_version)
+}
+
+impl ProgramConfig {
+    pub fn level_for(&self, xp: u64) -> u8 {
+        self.level_xp_thresholds.iter().take_while(|threshold| **threshold > 0 && **threshold <= xp).count() as u8
+    }
+
+    // None when stakes are uncapped
+    pub fn max_stake_for(&self, level: u8) -> Option<u64> {
+        if self.max_stake_base == 0 { return None; }
+        Some(self.max_stake_base.saturating_add(self.max_stake_per_level.saturating_mul(level as u64)))
+    }
+
+    pub fn refill_floor_for(&self, level: u8) -> u64 {
+        self.refill_floor_points.saturating_add(self.refill_bonus_per_level.saturating_mul(level as u64))
+    }
+
+    // Takes the resolver tip out of the keeper fund (less if the fund is running dry).
+    pub fn take_resolver_tip(&mut self) -> u64 {
+        let tip = self.resolver_tip_points.min(self.keeper_fund_points);
+        self.keeper_fund_points -= tip;
+        tip
+    }
+}
+
+impl UserProfile {
+    // Claims the index for a new bet PDA and advances the counter.
+    pub fn next_bet_index(&mut self) -> Result<u64> {
+        let bet_index = self.bet_count;
+        self.bet_count = self.bet_count.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+        Ok(bet_index)
+    }
+
+    // Moves the profile into the current season, resetting its balance. Returns the balance it had
+    // before, or None if it was already in the current season.
+    pub fn sync_season(&mut self, season_config: &SeasonConfig) -> Option<u64> {
+        if self.season_id == season_config.current_season_id { return None; }
+        let previous_points = self.points;
+        self.season_id = season_config.current_season_id;
+        self.points = season_config.starting_points;
+        self.season_net_pnl = 0;
+        self.reserved_points = 0; // Reserves from the old season are refunded into nothing
+        Some(previous_points)
+    }
+
+    // Payouts and refunds of bets staked in an earlier season are not carried into the new balance.
+    pub fn in_season_of(&self, bet: &ActiveBet) -> bool {
+        bet.season_id == self.season_id
+    }
+
+    // Moves points from the balance into a reserve (limit order or scheduled bet).
+    pub fn reserve_points(&mut self, amount: u64) -> Result<()> {
+        self.points = self.points.checked_sub(amount).ok_or_else(|| error!(BetError::InsufficientPoints))?;
+        self.reserved_points = self.reserved_points.checked_add(amount).ok_or(BetError::PriceCalculationOverflow)?;
         Ok(())
     }
 
-}   
+    // Call when a reserve is spent or refunded. Reserves from an earlier season were already dropped by sync_season.
+    pub fn release_reserved_points(&mut self, season_id: u64, amount: u64) {
+        if season_id == self.season_id { self.reserved_points = self.reserved_points.saturating_sub(amount); }
+    }
+
+    // New stakes and reserves can only come out of the current season's balance.
+    pub fn require_current_season(&self, season_config: Option<&SeasonConfig>) -> Result<()> {
+        if let Some(season_config) = season_config {
+            if self.season_id != season_config.current_season_id { return Err(error!(BetError::SeasonNotJoined)); }
+        }
+        Ok(())
+    }
+
+    // Credits points reserved or staked in the given season. Returns the amount actually credited.
+    pub fn credit_season_points(&mut self, season_id: u64, amount: u64) -> Result<u64> {
+        if season_id != self.season_id {
+            msg!("{} points from season {} not credited in season {}", amount, season_id, self.season_id);
+            return Ok(0);
+        }
+        self.points = self.points.checked_add(amount).ok_or(BetError::PriceCalculationOverflow)?;
+        Ok(amount)
+    }
+
+    // Credits a bet's payout or refund. Returns the amount actually credited.
+    pub fn credit_bet_payout(&mut self, bet: &ActiveBet, amount: u64) -> Result<u64> {
+        self.credit_season_points(bet.season_id, amount)
+    }
+
+    pub fn has_achievement(&self, achievement: Achievement) -> bool {
+        self.achievements & achievement.bit() != 0
+    }
+
+    // Sets the bit of every achievement the stats now qualify for and returns the newly unlocked ones.
+    // Conditions are cumulative, so an unlock missed by another instruction is picked up by the next check.
+    pub fn unlock_achievements(&mut self) -> Vec<Achievement> {
+        let unlocked: Vec<Achievement> = Achievement::ALL.into_iter()
+            .filter(|achievement| !self.has_achievement(*achievement) && achievement.is_earned(&self.stats))
+            .collect();
+        for achievement in &unlocked { self.achievements |= achievement.bit(); }
+        unlocked
+    }
+
+    pub fn record_bet_opened(&mut self, amount_staked: u64, timestamp: i64) -> Result<()> {
+        let stats = &mut self.stats;
+        stats.bets_placed = stats.bets_placed.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+        stats.total_staked = stats.total_staked.checked_add(amount_staked).ok_or(BetError::PriceCalculationOverflow)?;
+        stats.last_bet_timestamp = timestamp;
+        Ok(())
+    }
+
+    // Every stake, whether placed directly or reserved for a later bet, is capped by the profile's level.
+    pub fn check_stake_limit(&self, config: &ProgramConfig, amount: u64) -> Result<()> {
+        if let Some(max_stake) = config.max_stake_for(config.level_for(self.xp)) {
+            if amount > max_stake { return Err(error!(BetError::StakeAboveLevelLimit)); }
+        }
+        Ok(())
+    }
+
+    // Awards the XP for a bet that was opened. Returns the new level if the profile levelled up.
+    pub fn award_bet_xp(&mut self, config: &ProgramConfig, amount_staked: u64) -> Option<u8> {
+        let level = config.level_for(self.xp);
+        self.xp = self.xp.saturating_add(XP_PER_BET).saturating_add(amount_staked);
+        let new_level = config.level_for(self.xp);
+        if new_level <= level { return None; }
+        msg!("User {} reached level {} with {} XP", self.authority, new_level, self.xp);
+        Some(new_level)
+    }
+
+    // Call once the bet's status is final. `paid_to_bettor` is what this profile was credited
+    // (0 when a receipt holder collected the payout). Pushed, voided and cancelled bets leave the streak alone.
+    pub fn record_bet_settled(&mut self, bet: &ActiveBet, paid_to_bettor: u64) -> Result<()> {
+        let stats = &mut self.stats;
+        match bet.status {
+            BetStatus::Won => {
+                stats.bets_won = stats.bets_won.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+                stats.current_win_streak = stats.current_win_streak.saturating_add(1);
+                stats.best_win_streak = stats.best_win_streak.max(stats.current_win_streak);
+            }
+            BetStatus::Lost => {
+                stats.bets_lost = stats.bets_lost.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+                stats.current_win_streak = 0;
+            }
+            _ => {}
+        }
+        stats.total_paid_out = stats.total_paid_out.checked_add(paid_to_bettor).ok_or(BetError::PriceCalculationOverflow)?;
+        let bet_pnl = (paid_to_bettor as i64).checked_sub(bet.amount_staked as i64).ok_or(BetError::PriceCalculationOverflow)?;
+        stats.net_pnl = stats.net_pnl.checked_add(bet_pnl).ok_or(BetError::PriceCalculationOverflow)?;
+        if self.in_season_of(bet) {
+            self.season_net_pnl = self.season_net_pnl.checked_add(bet_pnl).ok_or(BetError::PriceCalculationOverflow)?;
+        }
+        if self.referrer != Pubkey::default() && bet.season_id == self.referral_season_id && bet_pnl > 0 {
+            self.referral_winnings = self.referral_winnings.checked_add(bet_pnl as u64).ok_or(BetError::PriceCalculationOverflow)?;
+        }
+        Ok(())
+    }
+}
+
+impl ActiveBet {
+    // Fills in a freshly created bet. Points must already have been deducted by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn open(&mut self, user: Pubkey, bet_index: u64, bump: u8, rent_payer: Pubkey, asset_name: String, initial_price: u64, opened_at: i64, duration_seconds: i64, direction: Direction, amount_staked: u64, kind: BetKind, season_id: u64) -> Result<()> {
+        self.user = user;
+        self.bet_index = bet_index;
+        self.bump = bump;
+        self.rent_payer = rent_payer;
+        self.asset_name = asset_name;
+        self.initial_price = initial_price;
+        self.opened_at = opened_at;
+        self.expiry_timestamp = opened_at.checked_add(duration_seconds).ok_or(BetError::TimestampOverflow)?;
+        self.direction = direction;
+        self.amount_staked = amount_staked;
+        self.resolved_price = 0;
+        self.status = BetStatus::Active;
+        self.receipt_mint = Pubkey::default();
+        self.kind = kind;
+        self.season_id = season_id;
+        self.version = ACTIVE_BET_VERSION;
+        Ok(())
+    }
+
+    // Records the resolved price and outcome. Returns the payout owed (0 if the bet lost).
+    // An unchanged price is a push and refunds the stake, like a flat market round.
+    pub fn settle(&mut self, resolved_price: u64) -> Result<u64> {
+        self.resolved_price = resolved_price;
+        let won = match self.direction {
+            Direction::Up => resolved_price > self.initial_price,
+            Direction::Down => resolved_price < self.initial_price,
+        };
+        if resolved_price == self.initial_price {
+            self.status = BetStatus::Push;
+            Ok(self.amount_staked)
+        } else if won {
+            self.status = BetStatus::Won;
+            Ok(self.amount_staked.checked_mul(2).ok_or(BetError::PriceCalculationOverflow)?)
+        } else {
+            self.status = BetStatus::Lost;
+            Ok(0)
+        }
+    }
+}
+
+// --- Events ---
+// Emitted with emit_cpi! so indexers can decode them from inner instructions even when logs are truncated.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PointsChangeReason {
+    BetStake,
+    BetPayout,
+    BetRefund,
+    ResolverTip,
+    KeeperTip,
+    ReceiptSale,
+    ReceiptPurchase,
+    MarketStake,
+    MarketRedemption,
+    OrderReserve,
+    OrderRefund,
+    SeasonReset,
+    Refill,
+    ReferralBonus,
+    ReferralShare,
+}
+
+#[event]
+pub struct LevelUp {
+    pub user: Pubkey,
+    pub level: u8,
+    pub xp: u64,
+}
+
+#[event]
+pub struct AchievementUnlocked {
+    pub user: Pubkey,
+    pub achievement: Achievement,
+}
+
+#[event]
+pub struct ProfileCreated {
+    pub user: Pubkey,
+    pub points: u64,
+}
+
+#[event]
+pub struct BetOpened {
+    pub user: Pubkey,
+    pub bet: Pubkey,
+    pub bet_index: u64,
+    pub kind: BetKind,
+    pub direction: Direction,
+    pub amount_staked: u64,
+    pub initial_price: u64,
+    pub expiry_timestamp: i64,
+}
+
+#[event]
+pub struct BetResolved {
+    pub user: Pubkey,
+    pub bet: Pubkey,
+    pub bet_index: u64,
+    pub status: BetStatus, // Won, Lost, Voided or Cancelled
+    pub resolved_price: u64,
+    pub payout: u64,       // Points credited (refund for voided/cancelled bets)
+    pub resolver: Pubkey,
+}
+
+#[event]
+pub struct PointsChanged {
+    pub user: Pubkey,
+    pub delta: i64,
+    pub new_balance: u64,
+    pub reason: PointsChangeReason,
+}
+
+#[event]
+pub struct DelegationChanged {
+    pub user: Pubkey,
+    pub is_delegated: bool,
+    pub nonce: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Undelegated {
+    pub user: Pubkey,
+    pub auth_state: Pubkey,
+}
+
+#[event]
+pub struct SeasonRewardClaimed {
+    pub season_id: u64,
+    pub user: Pubkey,
+    pub index: u32,
+    pub amount: u64,
+}
+
+impl BetOpened {
+    pub fn from_bet(bet: &Account<ActiveBet>) -> Self {
+        BetOpened {
+            user: bet.user,
+            bet: bet.key(),
+            bet_index: bet.bet_index,
+            kind: bet.kind,
+            direction: bet.direction,
+            amount_staked: bet.amount_staked,
+            initial_price: bet.initial_price,
+            expiry_timestamp: bet.expiry_timestamp,
+        }
+    }
+}
+
+impl BetResolved {
+    pub fn from_bet(bet: &Account<ActiveBet>, payout: u64, resolver: Pubkey) -> Self {
+        BetResolved {
+            user: bet.user,
+            bet: bet.key(),
+            bet_index: bet.bet_index,
+            status: bet.status,
+            resolved_price: bet.resolved_price,
+            payout,
+            resolver,
+        }
+    }
+}
+
+// --- Program Module ---
+#[ephemeral]
+#[program]
+pub mod bs_bet {
+    use super::*;
+
+    pub fn create_user_profile(ctx: Context<CreateUserProfile>) -> Result<()> {
+        let user_profile = &mut ctx.accounts.user_profile;
+        let is_new_profile = user_profile.authority == Pubkey::default();
+        if let Some(referrer_profile) = ctx.accounts.referrer_profile.as_ref() {
+            if !is_new_profile { return Err(error!(BetError::ReferralOnExistingProfile)); }
+            if referrer_profile.authority == ctx.accounts.user_authority.key() { return Err(error!(BetError::SelfReferral)); }
+            // The referee is brand new, so the only possible cycle is the referrer having been referred by them
+            if referrer_profile.referrer == ctx.accounts.user_authority.key() { return Err(error!(BetError::ReferralCycle)); }
+            user_profile.referrer = referrer_profile.authority;
+            user_profile.referral_season_id = ctx.accounts.season_config.as_ref().map_or(0, |season_config| season_config.current_season_id);
+            msg!("Referred by {} for season {}", user_profile.referrer, user_profile.referral_season_id);
+        }
+        // Calling this again on an existing profile must not reset its balance
+        if is_new_profile {
+            user_profile.authority = *ctx.accounts.user_authority.key;
+            user_profile.points = INITIAL_USER_POINTS;
+            user_profile.bump = ctx.bumps.user_profile;
+            user_profile.version = USER_PROFILE_VERSION;
+        }
+
+        let auth_state = &mut ctx.accounts.user_auth_state_for_profile_creation;
+        if auth_state.user_authority == Pubkey::default() { // Initialize only if new
+            auth_state.user_authority = *ctx.accounts.user_authority.key;
+            auth_state.is_delegated = false; // Default to standard mode
+            auth_state.delegation_timestamp = 0;
+            auth_state.nonce = 0;
+            auth_state.bump = ctx.bumps.user_auth_state_for_profile_creation;
+            auth_state.version = USER_AUTH_STATE_VERSION;
+        }
+        msg!("User profile created/updated. Auth state (is_delegated={}) initialized if new.", auth_state.is_delegated);
+        if is_new_profile {
+            emit_cpi!(ProfileCreated { user: ctx.accounts.user_profile.authority, points: ctx.accounts.user_profile.points });
+        }
+        Ok(())
+    }
+
+    pub fn manage_delegation(
+        ctx: Context<ManageDelegation>,
+        delegation_action: u8,
+        user_signed_message: Vec<u8>,
+        _signature: [u8; 64]) -> Result<()> {
+        let auth_state = &mut ctx.accounts.user_auth_state;
+        let user_key = ctx.accounts.user_authority.key();
+        let clock = Clock::get()?;
+
+        if delegation_action == 1 { // User intends to delegate ("Enable Quick Bets")
+            if auth_state.user_authority == Pubkey::default() {
+                auth_state.user_authority = user_key;
+                auth_state.bump = ctx.bumps.user_auth_state;
+                auth_state.nonce = 0;
+                auth_state.version = USER_AUTH_STATE_VERSION;
+            } else if auth_state.user_authority != user_key {
+                return Err(error!(BetError::UserProfileAuthorityMismatch));
+            }
+            // Client-side should ideally check if already delegated before calling.
+            // If called again while is_delegated = true, it effectively re-verifies for current nonce.
+            if auth_state.is_delegated && auth_state.nonce > 0 { // If already delegated and nonce was incremented
+                 msg!("Already processed for delegation. If MB SDK call failed, client can retry delegate_auth_state.");
+                 // No error, allow client to proceed to delegate_auth_state if needed.
+                 // Or, if nonce check is critical:
+                 // return Err(error!(BetError::AlreadyDelegated));
+            }
+
+
+            let current_nonce = auth_state.nonce;
+            let expected_message = create_delegation_message(&user_key, current_nonce);
+            if user_signed_message != expected_message.as_bytes() {
+                msg!("Invalid signed message content. Expected for nonce {}.", current_nonce);
+                return Err(error!(BetError::InvalidDelegationSignature));
+            }
+
+            // --- ED25519 VERIFICATION SKIPPED (Hackathon Compromise) ---
+            msg!("DEMO MODE: Skipping on-chain Ed25519 signature verification for manage_delegation.");
+            // --- END OF SKIPPED VERIFICATION ---
+
+            auth_state.is_delegated = true; // Set to true: ready for MagicBlock SDK call
+            auth_state.delegation_timestamp = clock.unix_timestamp;
+            auth_state.nonce = auth_state.nonce.checked_add(1).ok_or(BetError::TimestampOverflow)?;
+            msg!("UserAuthState ready for MagicBlock SDK (is_delegated=true). Nonce incremented to {}.", auth_state.nonce);
+            emit_cpi!(DelegationChanged { user: user_key, is_delegated: true, nonce: auth_state.nonce, timestamp: clock.unix_timestamp });
+
+        } else if delegation_action == 0 { // User intends to undelegate ("Disable Quick Bets")
+        
+        // This action is called AFTER undelegate_from_magicblock has returned ownership.
+        if auth_state.user_authority != user_key {
+            return Err(error!(BetError::UserProfileAuthorityMismatch));
+        }
+        // No need to check !auth_state.is_delegated, we are explicitly setting it.
+        auth_state.is_delegated = false;
+        // Optionally reset nonce or timestamp here if desired after full undelegation.
+        // auth_state.nonce = 0; // Example
+        msg!("UserAuthState locally marked as not delegated (is_delegated=false).");
+        emit_cpi!(DelegationChanged { user: user_key, is_delegated: false, nonce: auth_state.nonce, timestamp: clock.unix_timestamp });
+        } else {
+            return Err(error!(BetError::InvalidDelegationSignature)); // Or InvalidDelegationAction
+        }
+        Ok(())
+    }
+
+    pub fn delegate_auth_state(ctx: Context<DelegateAuthState>) -> Result<()> {
+        // The `#[delegate]` macro on the `DelegateAuthState` struct makes the `delegate_pda` method
+        // available on `ctx.accounts` (which is an instance of `DelegateAuthState`).
+        // The method uses the field marked with `#[account(del)]` (i.e., `ctx.accounts.pda`)
+        // as the target account for delegation.
+    
+        // The seeds passed here should be the seeds used to derive the PDA that
+        // `ctx.accounts.pda` (the AccountInfo) points to.
+        // Your UserAuthState PDA is derived with seeds: [b"auth_state", payer.key()]
+        msg!("Attempting to delegate PDA: {} with payer: {}", ctx.accounts.pda.key(), ctx.accounts.payer.key());
+        ctx.accounts.delegate_pda( // Calling the method on ctx.accounts
+            // First argument is typically the fee payer for the delegation transaction,
+            // but delegate_pda might just need it to confirm authority or for seeds.
+            // In the MB example, ctx.accounts.payer (Signer from DelegateInput) was used.
+            &ctx.accounts.payer,
+            // Seeds for the PDA being delegated (UserAuthState)
+            // These are the seeds used to *find* or *verify* the `ctx.accounts.pda`.
+            // The `pda` field itself is already resolved to a PublicKey.
+            // MagicBlock's example just passed `&[TEST_PDA_SEED]`.
+            // Your seeds are `b"auth_state"` and `payer.key()`.
+            &[
+                b"auth_state".as_ref(),
+                ctx.accounts.payer.key().as_ref() // payer.key() IS the second seed for your UserAuthState PDA
+            ],
+            DelegateConfig::default(),
+        )?;
+    
+        msg!("UserAuthState PDA ({}) successfully delegated via MagicBlock SDK by payer: {}", ctx.accounts.pda.key(), ctx.accounts.payer.key());
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_bet(
+        ctx: Context<OpenBetAccounts>,
+        asset_name_arg: String,
+        direction_arg: Direction,
+        amount_arg: u64,
+        duration_seconds_arg: i64,
+        user_authority_for_pdas: Pubkey,
+        bet_index_arg: u64,
+        expected_price_arg: Option<u64>, // Price the user saw, in OUR_PRICE_DECIMALS; None disables the check
+        max_deviation_bps_arg: u16,
+        idempotency_key_arg: Option<[u8; 16]>) -> Result<()> { // Requires the idempotency_log account
+        let auth_state = &mut ctx.accounts.user_auth_state;
+
+        // Initialize UserAuthState if it's new
+        if auth_state.user_authority == Pubkey::default() {
+            auth_state.user_authority = user_authority_for_pdas;
+            auth_state.is_delegated = false; // Default to false for new users
+            auth_state.delegation_timestamp = 0;
+            auth_state.nonce = 0;
+            auth_state.bump = ctx.bumps.user_auth_state;
+            auth_state.version = USER_AUTH_STATE_VERSION;
+        } else if auth_state.user_authority != user_authority_for_pdas {
+            // This ensures the PDA being used matches the intended user
+            return Err(error!(BetError::UserProfileAuthorityMismatch));
+        }
+
+        // This signer check is important for standard (non-MagicBlock-delegated) bets
+        if !auth_state.is_delegated && ctx.accounts.user_signer.key() != user_authority_for_pdas {
+            return Err(error!(BetError::UserProfileAuthorityMismatch));
+        }
+
+        let user_profile = &mut ctx.accounts.user_profile;
+        // Initialize UserProfile if it's new
+        if user_profile.authority == Pubkey::default() {
+            user_profile.authority = user_authority_for_pdas;
+            user_profile.points = INITIAL_USER_POINTS;
+            user_profile.bump = ctx.bumps.user_profile;
+            user_profile.version = USER_PROFILE_VERSION;
+            emit_cpi!(ProfileCreated { user: user_authority_for_pdas, points: user_profile.points });
+        } else if user_profile.authority != user_authority_for_pdas {
+            // This ensures the PDA being used matches the intended user
+            return Err(error!(BetError::UserProfileAuthorityMismatch));
+        }
+
+        // Seasons: the first bet of a season moves the profile into it and creates the season record
+        if let Some(season_config) = load_season_config(&ctx.accounts.season_config)? {
+            let season_record_info = ctx.accounts.season_record.as_ref().ok_or(BetError::SeasonRecordMissing)?.to_account_info();
+            let season_id_bytes = season_config.current_season_id.to_le_bytes();
+            let (expected_season_record, season_record_bump) = Pubkey::find_program_address(
+                &[b"season_record".as_ref(), user_authority_for_pdas.as_ref(), season_id_bytes.as_ref()], ctx.program_id);
+            if season_record_info.key() != expected_season_record { return Err(error!(BetError::SeasonRecordMissing)); }
+
+            let season_record = if season_record_info.data_is_empty() {
+                if let Some(previous_points) = user_profile.sync_season(&season_config) {
+                    emit_cpi!(PointsChanged { user: user_profile.authority, delta: (user_profile.points as i64).checked_sub(previous_points as i64).ok_or(BetError::PriceCalculationOverflow)?, new_balance: user_profile.points, reason: PointsChangeReason::SeasonReset });
+                }
+                let signer_seeds: &[&[&[u8]]] = &[&[b"season_record".as_ref(), user_authority_for_pdas.as_ref(), season_id_bytes.as_ref(), &[season_record_bump]]];
+                create_pda_account(&ctx.accounts.user_signer.to_account_info(), &season_record_info, &ctx.accounts.system_program.to_account_info(), USER_SEASON_RECORD_SPACE, ctx.program_id, signer_seeds)?;
+                msg!("User {} joined season {} with {} points", user_authority_for_pdas, season_config.current_season_id, user_profile.points);
+                UserSeasonRecord {
+                    user: user_authority_for_pdas,
+                    season_id: season_config.current_season_id,
+                    starting_points: user_profile.points,
+                    joined_at: Clock::get()?.unix_timestamp,
+                    bets_placed: 1,
+                    bump: season_record_bump,
+                }
+            } else {
+                user_profile.require_current_season(Some(&season_config))?;
+                let mut season_record = UserSeasonRecord::try_deserialize(&mut &season_record_info.try_borrow_data()?[..])?;
+                season_record.bets_placed = season_record.bets_placed.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+                season_record
+            };
+            season_record.try_serialize(&mut &mut season_record_info.try_borrow_mut_data()?[..])?;
+        }
+
+        // --- Rest of your open_bet logic: validation, points, Pyth, set bet_account fields ---
+        // This part was generally correct in your versions.
+        let bet_account = &mut ctx.accounts.bet_account;
+        let clock = Clock::get()?;
+        let price_update_account = &ctx.accounts.pyth_price_feed;
+
+        // Validations
+        if asset_name_arg != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
+        if amount_arg == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if duration_seconds_arg <= 0 { return Err(error!(BetError::InvalidDuration)); }
+        if user_profile.points < amount_arg { return Err(error!(BetError::InsufficientPoints)); }
+        user_profile.check_stake_limit(&ctx.accounts.config, amount_arg)?;
+        // Bets are addressed by the profile's counter; a retried transaction targets the same PDA and fails
+        if bet_index_arg != user_profile.bet_count { return Err(error!(BetError::BetIndexMismatch)); }
+        let bet_index = user_profile.next_bet_index()?;
+
+        // A retry that re-derived the next bet index is caught by the idempotency key instead
+        if let Some(idempotency_key) = idempotency_key_arg {
+            let idempotency_log = ctx.accounts.idempotency_log.as_mut().ok_or(BetError::IdempotencyLogMissing)?;
+            if let Some(existing_bet_index) = idempotency_log.find(&idempotency_key) {
+                msg!("Idempotency key already used for bet {}", existing_bet_index);
+                return Err(error!(BetError::DuplicateIdempotencyKey));
+            }
+            idempotency_log.record(idempotency_key, bet_index);
+        }
+
+        // Deduct points
+        user_profile.points = user_profile.points.checked_sub(amount_arg).ok_or_else(|| error!(BetError::InsufficientPoints))?;
+        user_profile.record_bet_opened(amount_arg, clock.unix_timestamp)?;
+        if let Some(level) = user_profile.award_bet_xp(&ctx.accounts.config, amount_arg) {
+            emit_cpi!(LevelUp { user: user_authority_for_pdas, level, xp: user_profile.xp });
+        }
+        msg!("User {} points: {} -> {}", user_authority_for_pdas, user_profile.points + amount_arg, user_profile.points);
+        emit_cpi!(PointsChanged { user: user_authority_for_pdas, delta: -(amount_arg as i64), new_balance: user_profile.points, reason: PointsChangeReason::BetStake });
+
+        // Pyth Price & Adjustment
+        let adjusted_price = get_adjusted_sol_usd_price(price_update_account, &clock)?;
+        msg!("Calculated initial price for bet: {}", adjusted_price); // Log the price
+        check_price_slippage(adjusted_price, expected_price_arg, max_deviation_bps_arg)?;
+
+        // Set bet_account fields
+        bet_account.open(user_authority_for_pdas, bet_index, ctx.bumps.bet_account, ctx.accounts.user_signer.key(), asset_name_arg, adjusted_price, clock.unix_timestamp, duration_seconds_arg, direction_arg, amount_arg, BetKind::Standard, user_profile.season_id)?;
+
+        // Optional receipt NFT: mint exactly one token, then drop the mint authority so supply stays fixed
+        if let (Some(receipt_mint), Some(receipt_token_account), Some(token_program)) =
+            (&ctx.accounts.receipt_mint, &ctx.accounts.receipt_token_account, &ctx.accounts.token_program) {
+            if ctx.accounts.user_signer.key() != user_authority_for_pdas { return Err(error!(BetError::UserProfileAuthorityMismatch)); }
+            // The bet PDA is the receipt's mint authority
+            let bet_index_bytes = bet_index.to_le_bytes();
+            let bet_seeds: &[&[&[u8]]] = &[&[b"bet".as_ref(), user_authority_for_pdas.as_ref(), bet_index_bytes.as_ref(), &[bet_account.bump]]];
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    MintTo {
+                        mint: receipt_mint.to_account_info(),
+                        to: receipt_token_account.to_account_info(),
+                        authority: bet_account.to_account_info(),
+                    },
+                    bet_seeds,
+                ),
+                1,
+            )?;
+            token_interface::set_authority(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    SetAuthority {
+                        current_authority: bet_account.to_account_info(),
+                        account_or_mint: receipt_mint.to_account_info(),
+                    },
+                    bet_seeds,
+                ),
+                AuthorityType::MintTokens,
+                None,
+            )?;
+            bet_account.receipt_mint = receipt_mint.key();
+            msg!("Receipt NFT minted: {}", receipt_mint.key());
+        }
+
+        for achievement in user_profile.unlock_achievements() {
+            msg!("Achievement unlocked: {:?}", achievement);
+            emit_cpi!(AchievementUnlocked { user: user_profile.authority, achievement });
+        }
+
+        msg!("Bet opened. UserAuthState.is_delegated: {}", auth_state.is_delegated);
+        emit_cpi!(BetOpened::from_bet(bet_account));
+        Ok(())
+    }
+
+    pub fn resolve_bet(ctx: Context<ResolveBetAccounts>) -> Result<()> {
+        // --- (Your existing resolve_bet logic, ensure Pyth price adjustment is complete) ---
+        let bet_account = &mut ctx.accounts.bet_account;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let clock = &ctx.accounts.clock;
+        let price_update_account = &ctx.accounts.pyth_price_feed;
+        let auth_state = &ctx.accounts.user_auth_state; // For logging
+
+        if bet_account.status != BetStatus::Active { return Err(error!(BetError::BetNotActiveOrAlreadyResolved));}
+        if clock.unix_timestamp <= bet_account.expiry_timestamp { return Err(error!(BetError::BetNotYetExpired));}
+        // PDA authority checks are handled by constraints on UserProfile and UserAuthState in ResolveBetAccounts
+
+        let adjusted_resolved_price = get_adjusted_sol_usd_price(price_update_account, clock)?;
+        msg!("Resolved price: {}", adjusted_resolved_price);
+        let payout_amount = bet_account.settle(adjusted_resolved_price)?;
+        let paid_to_holder = payout_amount > 0
+            && receipt_pays_holder(bet_account, ctx.accounts.receipt_token_account.as_ref(), ctx.accounts.receipt_holder_profile.as_ref())?;
+        let paid_to_bettor = if paid_to_holder || !user_profile.in_season_of(bet_account) { 0 } else { payout_amount };
+        user_profile.record_bet_settled(bet_account, paid_to_bettor)?;
+        for achievement in user_profile.unlock_achievements() {
+            msg!("Achievement unlocked: {:?}", achievement);
+            emit_cpi!(AchievementUnlocked { user: user_profile.authority, achievement });
+        }
+        if payout_amount > 0 {
+            // Bets with a receipt NFT pay whoever holds the receipt at settlement
+            let payout_profile = if paid_to_holder {
+                ctx.accounts.receipt_holder_profile.as_mut().ok_or(BetError::ReceiptAccountsMissing)?
+            } else {
+                user_profile
+            };
+            let credited = payout_profile.credit_bet_payout(bet_account, payout_amount)?;
+            bet_account.amount_credited = credited;
+            msg!("Bet {:?}! Payout: {} to {}. New points: {}", bet_account.status, credited, payout_profile.authority, payout_profile.points);
+            if credited > 0 {
+                emit_cpi!(PointsChanged { user: payout_profile.authority, delta: credited as i64, new_balance: payout_profile.points, reason: PointsChangeReason::BetPayout });
+            }
+        } else {
+            msg!("Bet LOST. Points: {}", user_profile.points);
+        }
+
+        // Third-party resolvers earn a tip from the keeper fund
+        if let Some(resolver_profile) = ctx.accounts.resolver_profile.as_mut() {
+            // Each profile must be loaded once, or the stale copy would overwrite the payout on exit
+            let is_payout_profile = resolver_profile.key() == ctx.accounts.user_profile.key()
+                || ctx.accounts.receipt_holder_profile.as_ref().is_some_and(|holder_profile| holder_profile.key() == resolver_profile.key());
+            if is_payout_profile { return Err(error!(BetError::DuplicateProfileAccount)); }
+            if ctx.accounts.resolver_signer.key() != bet_account.user {
+                let tip = ctx.accounts.config.take_resolver_tip();
+                resolver_profile.points = resolver_profile.points.checked_add(tip).ok_or(BetError::PriceCalculationOverflow)?;
+                msg!("Resolver tip: {}. Resolver points: {}", tip, resolver_profile.points);
+                emit_cpi!(PointsChanged { user: resolver_profile.authority, delta: tip as i64, new_balance: resolver_profile.points, reason: PointsChangeReason::ResolverTip });
+            }
+        }
+        msg!("Bet resolved. User: {}. Resolver: {}. Mode: {}.", bet_account.user, ctx.accounts.resolver_signer.key(), if auth_state.is_delegated {"Quick"} else {"Standard"});
+        emit_cpi!(BetResolved::from_bet(bet_account, payout_amount, ctx.accounts.resolver_signer.key()));
+
+        // Re-rank every profile this resolution touched
+        let mut touched_profiles: Vec<&UserProfile> = vec![&ctx.accounts.user_profile];
+        touched_profiles.extend(ctx.accounts.receipt_holder_profile.as_deref());
+        touched_profiles.extend(ctx.accounts.resolver_profile.as_deref());
+        for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+            leaderboard.rerank(&touched_profiles, clock.unix_timestamp);
+        }
+        Ok(())
+    }
+
+    // Lets the bettor undo a fresh bet: only within the cancel window and while the oracle
+    // price is still close to the bet's initial price. The fee goes to the keeper fund.
+    pub fn cancel_bet(ctx: Context<CancelBet>) -> Result<()> {
+        let bet_account = &mut ctx.accounts.bet_account;
+        let config = &mut ctx.accounts.config;
+        let clock = Clock::get()?;
+
+        if bet_account.receipt_mint != Pubkey::default() { return Err(error!(BetError::ReceiptBetNotCancellable)); }
+        let cancel_deadline = bet_account.opened_at.checked_add(config.cancel_window_seconds).ok_or(BetError::TimestampOverflow)?;
+        if clock.unix_timestamp > cancel_deadline { return Err(error!(BetError::CancelWindowElapsed)); }
+        // Once expired the outcome is known; cancelling a losing bet would make betting risk-free
+        if clock.unix_timestamp >= bet_account.expiry_timestamp { return Err(error!(BetError::CancelWindowElapsed)); }
+
+        let (current_price, _) = read_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock, CANCEL_MAX_PRICE_AGE_SECONDS)?;
+        let price_move = current_price.abs_diff(bet_account.initial_price) as u128;
+        let max_move = (bet_account.initial_price as u128) * (config.cancel_max_price_move_bps as u128) / (BPS_DENOMINATOR as u128);
+        if price_move > max_move { return Err(error!(BetError::PriceMovedTooFarToCancel)); }
+
+        let fee = bet_account.amount_staked.checked_mul(config.cancel_fee_bps as u64).ok_or(BetError::PriceCalculationOverflow)? / BPS_DENOMINATOR;
+        let refund = bet_account.amount_staked - fee;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let refund = user_profile.credit_bet_payout(bet_account, refund)?;
+        config.keeper_fund_points = config.keeper_fund_points.checked_add(fee).ok_or(BetError::PriceCalculationOverflow)?;
+        bet_account.resolved_price = current_price;
+        bet_account.status = BetStatus::Cancelled;
+        bet_account.amount_credited = refund;
+        user_profile.record_bet_settled(bet_account, refund)?;
+
+        msg!("Bet {} cancelled. Refund: {}, Fee: {}. New points: {}", bet_account.bet_index, refund, fee, user_profile.points);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: refund as i64, new_balance: user_profile.points, reason: PointsChangeReason::BetRefund });
+        emit_cpi!(BetResolved::from_bet(bet_account, refund, ctx.accounts.user_signer.key()));
+        for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+            leaderboard.rerank(&[user_profile], clock.unix_timestamp);
+        }
+        Ok(())
+    }
+
+    // Refunds a bet that nobody managed to resolve within the grace period after expiry, while the
+    // sponsored oracle feed has no usable price. Permissionless; the stake goes back to the bettor, or to the
+    // receipt holder for bets with a receipt NFT.
+    pub fn void_bet(ctx: Context<VoidBet>) -> Result<()> {
+        let bet_account = &mut ctx.accounts.bet_account;
+        let clock = Clock::get()?;
+
+        let void_after = bet_account.expiry_timestamp.checked_add(ctx.accounts.config.void_grace_period_seconds).ok_or(BetError::TimestampOverflow)?;
+        if clock.unix_timestamp <= void_after { return Err(error!(BetError::VoidGracePeriodNotElapsed)); }
+        // Voiding is only for bets that cannot be resolved: with a usable price, resolve_bet must be used instead
+        if ctx.accounts.pyth_price_feed.key() != sol_usd_push_feed_address()? { return Err(error!(BetError::PythPriceFeedError)); }
+        if get_adjusted_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock).is_ok() { return Err(error!(BetError::BetStillResolvable)); }
+
+        let user_profile = &mut ctx.accounts.user_profile;
+        bet_account.status = BetStatus::Voided;
+        let refunded_to_holder = receipt_pays_holder(bet_account, ctx.accounts.receipt_token_account.as_ref(), ctx.accounts.receipt_holder_profile.as_ref())?;
+        let refunded_to_bettor = if refunded_to_holder || !user_profile.in_season_of(bet_account) { 0 } else { bet_account.amount_staked };
+        user_profile.record_bet_settled(bet_account, refunded_to_bettor)?;
+        let refund_profile = if refunded_to_holder {
+            ctx.accounts.receipt_holder_profile.as_mut().ok_or(BetError::ReceiptAccountsMissing)?
+        } else {
+            user_profile
+        };
+        let refund = refund_profile.credit_bet_payout(bet_account, bet_account.amount_staked)?;
+        bet_account.amount_credited = refund;
+
+        msg!("Bet {} of {} voided. Refunded {} to {}. New points: {}", bet_account.bet_index, bet_account.user, refund, refund_profile.authority, refund_profile.points);
+        if refund > 0 {
+            emit_cpi!(PointsChanged { user: refund_profile.authority, delta: refund as i64, new_balance: refund_profile.points, reason: PointsChangeReason::BetRefund });
+        }
+        emit_cpi!(BetResolved::from_bet(bet_account, refund, ctx.accounts.voider.key()));
+
+        let mut touched_profiles: Vec<&UserProfile> = vec![&ctx.accounts.user_profile];
+        touched_profiles.extend(ctx.accounts.receipt_holder_profile.as_deref());
+        for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+            leaderboard.rerank(&touched_profiles, clock.unix_timestamp);
+        }
+        Ok(())
+    }
+
+    // Settles many expired bets against one price update.
+    // remaining_accounts: [bet_account, user_profile] pairs, all writable. Ineligible bets and invalid pairs are skipped.
+    pub fn resolve_bets<'info>(ctx: Context<'_, '_, 'info, 'info, ResolveBets<'info>>) -> Result<Vec<BetResolutionResult>> {
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        if ctx.remaining_accounts.is_empty() || !pairs.remainder().is_empty() { return Err(error!(BetError::InvalidRemainingAccounts)); }
+        if pairs.len() > MAX_BATCH_RESOLVE_BETS { return Err(error!(BetError::TooManyBetsInBatch)); }
+
+        let clock = Clock::get()?;
+        let resolver = ctx.accounts.resolver_signer.key();
+        let resolved_price = get_adjusted_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock)?;
+        msg!("Batch resolving {} bets at price {}", pairs.len(), resolved_price);
+
+        let mut total_tip = 0u64;
+        let mut results = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            // A bet closed or settled since the batch was built must not sink the rest of it
+            let accounts = if pair[0].is_writable && pair[1].is_writable {
+                Account::<ActiveBet>::try_from(&pair[0]).ok().zip(Account::<UserProfile>::try_from(&pair[1]).ok())
+            } else {
+                None
+            };
+            let Some((mut bet_account, mut user_profile)) = accounts else {
+                results.push(BetResolutionResult { bet: pair[0].key(), outcome: BatchResolveOutcome::SkippedInvalidAccount, payout: 0 });
+                continue;
+            };
+
+            let skipped = if bet_account.status != BetStatus::Active {
+                Some(BatchResolveOutcome::SkippedNotActive)
+            } else if clock.unix_timestamp <= bet_account.expiry_timestamp {
+                Some(BatchResolveOutcome::SkippedNotExpired)
+            } else if user_profile.authority != bet_account.user {
+                Some(BatchResolveOutcome::SkippedProfileMismatch)
+            } else if bet_account.receipt_mint != Pubkey::default() {
+                Some(BatchResolveOutcome::SkippedReceiptBet)
+            } else {
+                None
+            };
+            if let Some(outcome) = skipped {
+                results.push(BetResolutionResult { bet: bet_account.key(), outcome, payout: 0 });
+                continue;
+            }
+
+            let payout_amount = bet_account.settle(resolved_price)?;
+            let payout_amount = user_profile.credit_bet_payout(&bet_account, payout_amount)?;
+            bet_account.amount_credited = payout_amount;
+            user_profile.record_bet_settled(&bet_account, payout_amount)?;
+            // Write back immediately: the same profile may appear again in a later pair
+            bet_account.exit(ctx.program_id)?;
+            user_profile.exit(ctx.program_id)?;
+            if bet_account.user != resolver && ctx.accounts.resolver_profile.is_some() {
+                total_tip = total_tip.checked_add(ctx.accounts.config.take_resolver_tip()).ok_or(BetError::PriceCalculationOverflow)?;
+            }
+
+            // A won bet from an earlier season credits nothing but is still a win
+            let outcome = match bet_account.status {
+                BetStatus::Won => BatchResolveOutcome::Won,
+                BetStatus::Push => BatchResolveOutcome::Push,
+                _ => BatchResolveOutcome::Lost,
+            };
+            msg!("Bet {} of {}: {:?}. Payout: {}", bet_account.bet_index, bet_account.user, outcome, payout_amount);
+            if payout_amount > 0 {
+                emit_cpi!(PointsChanged { user: user_profile.authority, delta: payout_amount as i64, new_balance: user_profile.points, reason: PointsChangeReason::BetPayout });
+            }
+            emit_cpi!(BetResolved::from_bet(&bet_account, payout_amount, resolver));
+            for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+                leaderboard.rerank(&[&user_profile], clock.unix_timestamp);
+            }
+            results.push(BetResolutionResult { bet: bet_account.key(), outcome, payout: payout_amount });
+        }
+
+        if let Some(resolver_profile) = ctx.accounts.resolver_profile.as_mut() {
+            // The resolver's own profile may also have been a remaining account: reload its written state
+            resolver_profile.reload()?;
+            resolver_profile.points = resolver_profile.points.checked_add(total_tip).ok_or(BetError::PriceCalculationOverflow)?;
+            msg!("Resolver tip: {}. Resolver points: {}", total_tip, resolver_profile.points);
+            emit_cpi!(PointsChanged { user: resolver_profile.authority, delta: total_tip as i64, new_balance: resolver_profile.points, reason: PointsChangeReason::ResolverTip });
+            for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+                leaderboard.rerank(&[resolver_profile], clock.unix_timestamp);
+            }
+        }
+        Ok(results)
+    }
+
+    // --- Program Config ---
+    // Only the program's upgrade authority can create the config; it becomes the admin.
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.resolver_tip_points = 0;
+        config.keeper_fund_points = 0;
+        config.bump = ctx.bumps.config;
+        config.version = PROGRAM_CONFIG_VERSION;
+        config.void_grace_period_seconds = DEFAULT_VOID_GRACE_PERIOD_SECONDS;
+        config.cancel_window_seconds = DEFAULT_CANCEL_WINDOW_SECONDS;
+        config.cancel_max_price_move_bps = DEFAULT_CANCEL_MAX_PRICE_MOVE_BPS;
+        config.cancel_fee_bps = 0;
+        config.refill_floor_points = DEFAULT_REFILL_FLOOR_POINTS;
+        config.refill_cooldown_seconds = DEFAULT_REFILL_COOLDOWN_SECONDS;
+        config.referral_bonus_points = DEFAULT_REFERRAL_BONUS_POINTS;
+        config.referral_bonus_bets = DEFAULT_REFERRAL_BONUS_BETS;
+        config.referral_share_bps = DEFAULT_REFERRAL_SHARE_BPS;
+        config.market_round_staking_seconds = DEFAULT_MARKET_ROUND_STAKING_SECONDS;
+        config.max_market_round_duration_seconds = DEFAULT_MAX_MARKET_ROUND_DURATION_SECONDS;
+        msg!("Program config initialized. Admin: {}", config.admin);
+        Ok(())
+    }
+
+    pub fn update_config(ctx: Context<AdminConfig>, params: ConfigUpdateParams) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        if let Some(resolver_tip_points) = params.resolver_tip_points { config.resolver_tip_points = resolver_tip_points; }
+        if let Some(void_grace_period_seconds) = params.void_grace_period_seconds {
+            if void_grace_period_seconds < 0 { return Err(error!(BetError::InvalidDuration)); }
+            config.void_grace_period_seconds = void_grace_period_seconds;
+        }
+        if let Some(cancel_window_seconds) = params.cancel_window_seconds {
+            if cancel_window_seconds < 0 { return Err(error!(BetError::InvalidDuration)); }
+            config.cancel_window_seconds = cancel_window_seconds;
+        }
+        if let Some(cancel_max_price_move_bps) = params.cancel_max_price_move_bps { config.cancel_max_price_move_bps = cancel_max_price_move_bps; }
+        if let Some(cancel_fee_bps) = params.cancel_fee_bps {
+            if cancel_fee_bps as u64 > BPS_DENOMINATOR { return Err(error!(BetError::InvalidBasisPoints)); }
+            config.cancel_fee_bps = cancel_fee_bps;
+        }
+        if let Some(refill_floor_points) = params.refill_floor_points { config.refill_floor_points = refill_floor_points; }
+        if let Some(refill_cooldown_seconds) = params.refill_cooldown_seconds {
+            if refill_cooldown_seconds < 0 { return Err(error!(BetError::InvalidDuration)); }
+            config.refill_cooldown_seconds = refill_cooldown_seconds;
+        }
+        if let Some(referral_bonus_points) = params.referral_bonus_points { config.referral_bonus_points = referral_bonus_points; }
+        if let Some(referral_bonus_bets) = params.referral_bonus_bets { config.referral_bonus_bets = referral_bonus_bets; }
+        if let Some(referral_share_bps) = params.referral_share_bps {
+            if referral_share_bps as u64 > BPS_DENOMINATOR { return Err(error!(BetError::InvalidBasisPoints)); }
+            config.referral_share_bps = referral_share_bps;
+        }
+        if let Some(level_xp_thresholds) = params.level_xp_thresholds {
+            let used_levels = level_xp_thresholds.iter().take_while(|threshold| **threshold > 0).count();
+            let ascending = level_xp_thresholds[..used_levels].windows(2).all(|pair| pair[0] < pair[1]);
+            let trailing_zeros = level_xp_thresholds[used_levels..].iter().all(|threshold| *threshold == 0);
+            if !ascending || !trailing_zeros { return Err(error!(BetError::InvalidLevelThresholds)); }
+            config.level_xp_thresholds = level_xp_thresholds;
+        }
+        if let Some(max_stake_base) = params.max_stake_base { config.max_stake_base = max_stake_base; }
+        if let Some(max_stake_per_level) = params.max_stake_per_level { config.max_stake_per_level = max_stake_per_level; }
+        if let Some(refill_bonus_per_level) = params.refill_bonus_per_level { config.refill_bonus_per_level = refill_bonus_per_level; }
+        if let Some(market_round_staking_seconds) = params.market_round_staking_seconds {
+            if market_round_staking_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+            config.market_round_staking_seconds = market_round_staking_seconds;
+        }
+        if let Some(max_market_round_duration_seconds) = params.max_market_round_duration_seconds {
+            if max_market_round_duration_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+            config.max_market_round_duration_seconds = max_market_round_duration_seconds;
+        }
+        msg!("Program config updated. Resolver tip: {}, Void grace period: {}s, Cancel window: {}s", config.resolver_tip_points, config.void_grace_period_seconds, config.cancel_window_seconds);
+        Ok(())
+    }
+
+    pub fn fund_keeper_pool(ctx: Context<AdminConfig>, amount_arg: u64) -> Result<()> {
+        if amount_arg == 0 { return Err(error!(BetError::ZeroAmount)); }
+        let config = &mut ctx.accounts.config;
+        config.keeper_fund_points = config.keeper_fund_points.checked_add(amount_arg).ok_or(BetError::PriceCalculationOverflow)?;
+        msg!("Keeper fund topped up by {}. Balance: {}", amount_arg, config.keeper_fund_points);
+        Ok(())
+    }
+
+    // --- Achievement Badges ---
+    // Mints a non-transferable Token-2022 badge for an unlocked achievement. The badge mint is a PDA per
+    // (user, achievement), so each badge can only be minted once; its supply is fixed at 1.
+    pub fn mint_achievement_badge(ctx: Context<MintAchievementBadge>, achievement: Achievement) -> Result<()> {
+        if !ctx.accounts.user_profile.has_achievement(achievement) { return Err(error!(BetError::AchievementLocked)); }
+        let badge_mint = ctx.accounts.badge_mint.to_account_info();
+        if !badge_mint.data_is_empty() { return Err(error!(BetError::BadgeAlreadyMinted)); }
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let user_key = ctx.accounts.user_authority.key();
+        let badge_seeds: &[&[&[u8]]] = &[&[b"badge".as_ref(), user_key.as_ref(), &[achievement as u8], &[ctx.bumps.badge_mint]]];
+
+        let space = ExtensionType::try_calculate_account_len::<MintState>(&[ExtensionType::NonTransferable])?;
+        // The badge address is predictable, so it may already hold lamports sent to block a plain create_account
+        create_pda_account(&ctx.accounts.user_authority.to_account_info(), &badge_mint, &ctx.accounts.system_program.to_account_info(), space, &token_program.key(), badge_seeds)?;
+        token_interface::non_transferable_mint_initialize(CpiContext::new(
+            token_program.clone(),
+            NonTransferableMintInitialize { token_program_id: token_program.clone(), mint: badge_mint.clone() },
+        ))?;
+        // The badge mint is its own mint authority
+        token_interface::initialize_mint2(
+            CpiContext::new(token_program.clone(), InitializeMint2 { mint: badge_mint.clone() }),
+            BADGE_DECIMALS,
+            &badge_mint.key(),
+            None,
+        )?;
+        // Idempotent: anyone can create the holder's associated token account ahead of time
+        anchor_spl::associated_token::create_idempotent(CpiContext::new(
+            ctx.accounts.associated_token_program.to_account_info(),
+            anchor_spl::associated_token::Create {
+                payer: ctx.accounts.user_authority.to_account_info(),
+                associated_token: ctx.accounts.badge_token_account.to_account_info(),
+                authority: ctx.accounts.user_authority.to_account_info(),
+                mint: badge_mint.clone(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                token_program: token_program.clone(),
+            },
+        ))?;
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                MintTo { mint: badge_mint.clone(), to: ctx.accounts.badge_token_account.to_account_info(), authority: badge_mint.clone() },
+                badge_seeds,
+            ),
+            1,
+        )?;
+        token_interface::set_authority(
+            CpiContext::new_with_signer(
+                token_program,
+                SetAuthority { current_authority: badge_mint.clone(), account_or_mint: badge_mint.clone() },
+                badge_seeds,
+            ),
+            AuthorityType::MintTokens,
+            None,
+        )?;
+        msg!("Badge for {:?} minted to {}: {}", achievement, user_key, badge_mint.key());
+        Ok(())
+    }
+
+    // --- Profile Metadata ---
+    // Claiming a display name creates its NameRegistry PDA, so a taken name fails to initialize.
+    // Changing or clearing the name closes the registry of the previous one.
+    pub fn update_profile(ctx: Context<UpdateProfile>, params: ProfileUpdateParams) -> Result<()> {
+        let metadata = &mut ctx.accounts.profile_metadata;
+        let clock = Clock::get()?;
+        if metadata.authority == Pubkey::default() {
+            metadata.authority = ctx.accounts.user_authority.key();
+            metadata.bump = ctx.bumps.profile_metadata;
+        }
+
+        let name_change = params.display_name.filter(|name| *name != metadata.display_name);
+        match name_change {
+            Some(new_name) => {
+                match (metadata.display_name.is_empty(), ctx.accounts.old_name_registry.is_some()) {
+                    (false, false) => return Err(error!(BetError::NameRegistryMissing)),
+                    (true, true) => return Err(error!(BetError::UnexpectedNameRegistry)),
+                    _ => {}
+                }
+                if new_name.is_empty() {
+                    if ctx.accounts.new_name_registry.is_some() { return Err(error!(BetError::UnexpectedNameRegistry)); }
+                } else {
+                    validate_display_name(&new_name)?;
+                    let new_name_registry = ctx.accounts.new_name_registry.as_mut().ok_or(BetError::NameRegistryMissing)?;
+                    new_name_registry.owner = metadata.authority;
+                    new_name_registry.bump = ctx.bumps.new_name_registry.ok_or(BetError::NameRegistryMissing)?;
+                }
+                msg!("Display name changed from '{}' to '{}'", metadata.display_name, new_name);
+                metadata.display_name = new_name;
+            }
+            // Passing a registry without changing the name would close or create it needlessly
+            None => {
+                if ctx.accounts.old_name_registry.is_some() || ctx.accounts.new_name_registry.is_some() { return Err(error!(BetError::UnexpectedNameRegistry)); }
+            }
+        }
+        if let Some(avatar_uri) = params.avatar_uri {
+            validate_avatar_uri(&avatar_uri)?;
+            metadata.avatar_uri = avatar_uri;
+        }
+        if let Some(bio) = params.bio {
+            validate_bio(&bio)?;
+            metadata.bio = bio;
+        }
+        metadata.updated_at = clock.unix_timestamp;
+        msg!("Profile metadata updated for {}", metadata.authority);
+        Ok(())
+    }
+
+    // --- Refill Faucet ---
+    // Tops a busted balance back up to the configured floor, at most once per cooldown period.
+    pub fn claim_refill(ctx: Context<ClaimRefill>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let clock = Clock::get()?;
+        let refill_floor = config.refill_floor_for(config.level_for(user_profile.xp));
+        // Points parked in open orders and scheduled bets still belong to the user
+        let holdings = user_profile.points.saturating_add(user_profile.reserved_points);
+        if holdings >= refill_floor { return Err(error!(BetError::NotEligibleForRefill)); }
+        if user_profile.last_refill_at != 0 {
+            let next_refill_at = user_profile.last_refill_at.checked_add(config.refill_cooldown_seconds).ok_or(BetError::TimestampOverflow)?;
+            if clock.unix_timestamp < next_refill_at { return Err(error!(BetError::RefillOnCooldown)); }
+        }
+
+        let refill = refill_floor - holdings;
+        user_profile.points = user_profile.points.checked_add(refill).ok_or(BetError::PriceCalculationOverflow)?;
+        user_profile.last_refill_at = clock.unix_timestamp;
+        msg!("Refilled {} points for {}. New points: {}", refill, user_profile.authority, user_profile.points);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: refill as i64, new_balance: user_profile.points, reason: PointsChangeReason::Refill });
+        Ok(())
+    }
+
+    // --- Referrals ---
+    // Permissionless. Pays the one-time bonus to both parties once the referee has enough won or lost
+    // bets, and the referrer's share of the winnings the referee accrued in the referral season.
+    pub fn settle_referral(ctx: Context<SettleReferral>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let referee_profile = &mut ctx.accounts.referee_profile;
+        let referrer_profile = &mut ctx.accounts.referrer_profile;
+
+        let mut bonus = 0;
+        let decided_bets = referee_profile.stats.bets_won.saturating_add(referee_profile.stats.bets_lost);
+        if !referee_profile.referral_bonus_paid && decided_bets >= config.referral_bonus_bets {
+            referee_profile.referral_bonus_paid = true;
+            bonus = config.referral_bonus_points;
+        }
+        let share = ((referee_profile.referral_winnings as u128) * (config.referral_share_bps as u128) / (BPS_DENOMINATOR as u128)) as u64;
+        referee_profile.referral_winnings = 0;
+        if bonus == 0 && share == 0 { return Err(error!(BetError::NothingToSettle)); }
+
+        if bonus > 0 {
+            referee_profile.points = referee_profile.points.checked_add(bonus).ok_or(BetError::PriceCalculationOverflow)?;
+            referrer_profile.points = referrer_profile.points.checked_add(bonus).ok_or(BetError::PriceCalculationOverflow)?;
+            emit_cpi!(PointsChanged { user: referee_profile.authority, delta: bonus as i64, new_balance: referee_profile.points, reason: PointsChangeReason::ReferralBonus });
+            emit_cpi!(PointsChanged { user: referrer_profile.authority, delta: bonus as i64, new_balance: referrer_profile.points, reason: PointsChangeReason::ReferralBonus });
+        }
+        if share > 0 {
+            referrer_profile.points = referrer_profile.points.checked_add(share).ok_or(BetError::PriceCalculationOverflow)?;
+            emit_cpi!(PointsChanged { user: referrer_profile.authority, delta: share as i64, new_balance: referrer_profile.points, reason: PointsChangeReason::ReferralShare });
+        }
+        msg!("Referral settled for {}. Bonus: {}, Referrer share: {}", referee_profile.authority, bonus, share);
+        Ok(())
+    }
+
+    // --- Leaderboards ---
+    // Boards are re-ranked by resolve_bet for the profiles it touches.
+    pub fn initialize_leaderboard(ctx: Context<InitializeLeaderboard>, metric: LeaderboardMetric) -> Result<()> {
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.metric = metric;
+        leaderboard.season_id = 0;
+        leaderboard.entries = Vec::new();
+        leaderboard.bump = ctx.bumps.leaderboard;
+        msg!("Leaderboard {:?} initialized", metric);
+        Ok(())
+    }
+
+    // --- Seasons ---
+    // Balances are reset lazily: a profile moves into the current season on its next open_bet.
+    // end_season snapshots both leaderboards into a SeasonResult and starts the next season.
+    pub fn initialize_seasons(ctx: Context<InitializeSeasons>, starting_points: u64, season_duration_seconds: i64) -> Result<()> {
+        if starting_points == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if season_duration_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+        let clock = Clock::get()?;
+
+        let season_config = &mut ctx.accounts.season_config;
+        season_config.current_season_id = 1;
+        season_config.season_started_at = clock.unix_timestamp;
+        season_config.season_ends_at = clock.unix_timestamp.checked_add(season_duration_seconds).ok_or(BetError::TimestampOverflow)?;
+        season_config.season_duration_seconds = season_duration_seconds;
+        season_config.starting_points = starting_points;
+        season_config.bump = ctx.bumps.season_config;
+        for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+            leaderboard.season_id = season_config.current_season_id;
+            leaderboard.entries.clear();
+        }
+        msg!("Season 1 started. Starting points: {}. Ends at: {}", starting_points, season_config.season_ends_at);
+        Ok(())
+    }
+
+    // Moves the profile into the current season (resetting its balance) and creates its season record
+    pub fn join_season(ctx: Context<JoinSeason>) -> Result<()> {
+        let season_config = &ctx.accounts.season_config;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let clock = Clock::get()?;
+        if let Some(previous_points) = user_profile.sync_season(season_config) {
+            emit_cpi!(PointsChanged { user: user_profile.authority, delta: (user_profile.points as i64).checked_sub(previous_points as i64).ok_or(BetError::PriceCalculationOverflow)?, new_balance: user_profile.points, reason: PointsChangeReason::SeasonReset });
+        }
+
+        let season_record = &mut ctx.accounts.season_record;
+        season_record.user = user_profile.authority;
+        season_record.season_id = season_config.current_season_id;
+        season_record.starting_points = user_profile.points;
+        season_record.joined_at = clock.unix_timestamp;
+        season_record.bets_placed = 0;
+        season_record.bump = ctx.bumps.season_record;
+        msg!("User {} joined season {} with {} points", user_profile.authority, season_record.season_id, user_profile.points);
+        Ok(())
+    }
+
+    // Permissionless once the season's end time has passed
+    pub fn end_season(ctx: Context<EndSeason>) -> Result<()> {
+        let clock = Clock::get()?;
+        let season_config = &mut ctx.accounts.season_config;
+        if clock.unix_timestamp < season_config.season_ends_at { return Err(error!(BetError::SeasonNotOver)); }
+
+        let season_result = &mut ctx.accounts.season_result;
+        season_result.season_id = season_config.current_season_id;
+        season_result.started_at = season_config.season_started_at;
+        season_result.ended_at = clock.unix_timestamp;
+        season_result.points_standings = ctx.accounts.points_leaderboard.entries.clone();
+        season_result.pnl_standings = ctx.accounts.pnl_leaderboard.entries.clone();
+        season_result.bump = ctx.bumps.season_result;
+
+        season_config.current_season_id = season_config.current_season_id.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+        season_config.season_started_at = clock.unix_timestamp;
+        season_config.season_ends_at = clock.unix_timestamp.checked_add(season_config.season_duration_seconds).ok_or(BetError::TimestampOverflow)?;
+        for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+            leaderboard.season_id = season_config.current_season_id;
+            leaderboard.entries.clear();
+        }
+        msg!("Season {} ended with {} ranked users. Season {} ends at {}", season_result.season_id, season_result.points_standings.len(), season_config.current_season_id, season_config.season_ends_at);
+        Ok(())
+    }
+
+    // --- Season Rewards ---
+    // The admin posts a merkle root of (index, user_profile, amount) leaves for a finished season and
+    // funds the distributor's token account; users claim their leaf with a proof.
+    pub fn create_season_distributor(ctx: Context<CreateSeasonDistributor>, merkle_root: [u8; 32], num_claims: u32) -> Result<()> {
+        if num_claims == 0 || num_claims > MAX_DISTRIBUTOR_CLAIMS { return Err(error!(BetError::InvalidDistributorSize)); }
+        let distributor = &mut ctx.accounts.distributor;
+        distributor.season_id = ctx.accounts.season_result.season_id;
+        distributor.merkle_root = merkle_root;
+        distributor.reward_mint = ctx.accounts.reward_mint.key();
+        distributor.num_claims = num_claims;
+        distributor.total_claimed = 0;
+        distributor.claimed_bitmap = vec![0u8; (num_claims as usize).div_ceil(8)];
+        distributor.bump = ctx.bumps.distributor;
+        msg!("Season {} distributor created. Mint: {}, Claims: {}", distributor.season_id, distributor.reward_mint, num_claims);
+        Ok(())
+    }
+
+    pub fn claim_season_reward(ctx: Context<ClaimSeasonReward>, index: u32, amount: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        let distributor = &mut ctx.accounts.distributor;
+        if index >= distributor.num_claims { return Err(error!(BetError::ClaimIndexOutOfRange)); }
+        if distributor.is_claimed(index) { return Err(error!(BetError::RewardAlreadyClaimed)); }
+        let leaf = season_reward_leaf(index, &ctx.accounts.user_profile.key(), amount);
+        if !verify_merkle_proof(&proof, &distributor.merkle_root, leaf) { return Err(error!(BetError::InvalidMerkleProof)); }
+
+        distributor.set_claimed(index);
+        distributor.total_claimed = distributor.total_claimed.checked_add(amount).ok_or(BetError::PriceCalculationOverflow)?;
+
+        let season_id_bytes = distributor.season_id.to_le_bytes();
+        let distributor_seeds: &[&[&[u8]]] = &[&[b"season_distributor".as_ref(), season_id_bytes.as_ref(), &[distributor.bump]]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    mint: ctx.accounts.reward_mint.to_account_info(),
+                    to: ctx.accounts.claimant_token_account.to_account_info(),
+                    authority: distributor.to_account_info(),
+                },
+                distributor_seeds,
+            ),
+            amount,
+            ctx.accounts.reward_mint.decimals,
+        )?;
+
+        msg!("Season {} reward claimed. Index: {}, Amount: {}, User: {}", distributor.season_id, index, amount, ctx.accounts.claimant.key());
+        emit_cpi!(SeasonRewardClaimed { season_id: distributor.season_id, user: ctx.accounts.claimant.key(), index, amount });
+        Ok(())
+    }
+
+    // --- Bet Receipt Secondary Market ---
+    // A listed receipt sits in an escrow token account owned by the listing PDA until it is
+    // bought (points move buyer -> seller) or the listing is cancelled.
+    pub fn list_bet_receipt(ctx: Context<ListBetReceipt>, price_points: u64) -> Result<()> {
+        let bet_account = &ctx.accounts.bet_account;
+        let clock = Clock::get()?;
+        if bet_account.status != BetStatus::Active { return Err(error!(BetError::BetNotActiveOrAlreadyResolved)); }
+        if clock.unix_timestamp >= bet_account.expiry_timestamp { return Err(error!(BetError::ReceiptListingExpired)); }
+        if price_points == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if ctx.accounts.escrow_token_account.amount != 0 { return Err(error!(BetError::InvalidReceiptTokenAccount)); }
+
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.seller_token_account.to_account_info(),
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    to: ctx.accounts.escrow_token_account.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            ),
+            1,
+            RECEIPT_DECIMALS,
+        )?;
+
+        let listing = &mut ctx.accounts.listing;
+        listing.seller = ctx.accounts.seller.key();
+        listing.bet = bet_account.key();
+        listing.receipt_mint = ctx.accounts.receipt_mint.key();
+        listing.price_points = price_points;
+        listing.bump = ctx.bumps.listing;
+        listing.version = RECEIPT_LISTING_VERSION;
+        msg!("Receipt {} listed for {} points", listing.receipt_mint, price_points);
+        Ok(())
+    }
+
+    pub fn buy_bet_receipt(ctx: Context<BuyBetReceipt>) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        let bet_account = &ctx.accounts.bet_account;
+        let clock = Clock::get()?;
+        if bet_account.status != BetStatus::Active { return Err(error!(BetError::BetNotActiveOrAlreadyResolved)); }
+        if clock.unix_timestamp >= bet_account.expiry_timestamp { return Err(error!(BetError::ReceiptListingExpired)); }
+
+        let buyer_profile = &mut ctx.accounts.buyer_profile;
+        let seller_profile = &mut ctx.accounts.seller_profile;
+        if buyer_profile.points < listing.price_points { return Err(error!(BetError::InsufficientPoints)); }
+        buyer_profile.points = buyer_profile.points.checked_sub(listing.price_points).ok_or_else(|| error!(BetError::InsufficientPoints))?;
+        seller_profile.points = seller_profile.points.checked_add(listing.price_points).ok_or(BetError::PriceCalculationOverflow)?;
+
+        let listing_seeds: &[&[&[u8]]] = &[&[b"listing".as_ref(), listing.receipt_mint.as_ref(), &[listing.bump]]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    to: ctx.accounts.buyer_token_account.to_account_info(),
+                    authority: listing.to_account_info(),
+                },
+                listing_seeds,
+            ),
+            1,
+            RECEIPT_DECIMALS,
+        )?;
+        token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.escrow_token_account.to_account_info(),
+                destination: ctx.accounts.seller.to_account_info(),
+                authority: listing.to_account_info(),
+            },
+            listing_seeds,
+        ))?;
+
+        msg!("Receipt {} sold for {} points. Seller: {}, Buyer: {}", listing.receipt_mint, listing.price_points, listing.seller, ctx.accounts.buyer.key());
+        emit_cpi!(PointsChanged { user: buyer_profile.authority, delta: -(listing.price_points as i64), new_balance: buyer_profile.points, reason: PointsChangeReason::ReceiptPurchase });
+        emit_cpi!(PointsChanged { user: seller_profile.authority, delta: listing.price_points as i64, new_balance: seller_profile.points, reason: PointsChangeReason::ReceiptSale });
+        Ok(())
+    }
+
+    pub fn cancel_receipt_listing(ctx: Context<CancelReceiptListing>) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        let listing_seeds: &[&[&[u8]]] = &[&[b"listing".as_ref(), listing.receipt_mint.as_ref(), &[listing.bump]]];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    to: ctx.accounts.seller_token_account.to_account_info(),
+                    authority: listing.to_account_info(),
+                },
+                listing_seeds,
+            ),
+            1,
+            RECEIPT_DECIMALS,
+        )?;
+        token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.escrow_token_account.to_account_info(),
+                destination: ctx.accounts.seller.to_account_info(),
+                authority: listing.to_account_info(),
+            },
+            listing_seeds,
+        ))?;
+        msg!("Listing for receipt {} cancelled", listing.receipt_mint);
+        Ok(())
+    }
+
+    // --- Tokenized Market Rounds ---
+    // A round has one strike price for everyone. Staking mints UP or DOWN outcome tokens
+    // (Token-2022, 1 token per point staked) which can be transferred, held by other wallets
+    // or composed with other programs, and redeemed by whoever holds them once the round resolves:
+    // winning tokens pay 2 points each, and if the price ends flat every token refunds its 1 point.
+    pub fn initialize_market_round(
+        ctx: Context<InitializeMarketRound>,
+        round_id: u64,
+        asset_name_arg: String,
+        duration_seconds_arg: i64) -> Result<()> {
+        if asset_name_arg != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
+        let config = &ctx.accounts.config;
+        if duration_seconds_arg <= 0 || duration_seconds_arg > config.max_market_round_duration_seconds { return Err(error!(BetError::InvalidDuration)); }
+
+        let clock = Clock::get()?;
+        let (strike_price, _) = read_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock, MARKET_ROUND_MAX_PRICE_AGE_SECONDS)?;
+        // Stakes close well before expiry, so nobody can buy the side the price has already moved towards.
+        let staking_seconds = config.market_round_staking_seconds.min(duration_seconds_arg);
+
+        let market_round = &mut ctx.accounts.market_round;
+        market_round.round_id = round_id;
+        market_round.asset_name = asset_name_arg;
+        market_round.strike_price = strike_price;
+        market_round.start_timestamp = clock.unix_timestamp;
+        market_round.expiry_timestamp = clock.unix_timestamp.checked_add(duration_seconds_arg).ok_or(BetError::TimestampOverflow)?;
+        market_round.resolved_price = 0;
+        market_round.status = MarketRoundStatus::Open;
+        market_round.up_mint = ctx.accounts.up_mint.key();
+        market_round.down_mint = ctx.accounts.down_mint.key();
+        market_round.total_up_staked = 0;
+        market_round.total_down_staked = 0;
+        market_round.bump = ctx.bumps.market_round;
+        market_round.version = MARKET_ROUND_VERSION;
+        market_round.season_id = load_season_config(&ctx.accounts.season_config)?.map_or(0, |season_config| season_config.current_season_id);
+        market_round.lock_timestamp = clock.unix_timestamp.checked_add(staking_seconds).ok_or(BetError::TimestampOverflow)?;
+
+        msg!("Market round {} opened. Strike: {}. Expires at: {}", round_id, strike_price, market_round.expiry_timestamp);
+        Ok(())
+    }
+
+    pub fn stake_market_round(ctx: Context<StakeMarketRound>, direction_arg: Direction, amount_arg: u64) -> Result<()> {
+        let market_round = &mut ctx.accounts.market_round;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let clock = Clock::get()?;
+
+        if market_round.status != MarketRoundStatus::Open || clock.unix_timestamp >= market_round.lock_timestamp { return Err(error!(BetError::MarketRoundNotOpen)); }
+        if amount_arg == 0 { return Err(error!(BetError::ZeroAmount)); }
+        user_profile.require_current_season(load_season_config(&ctx.accounts.season_config)?.as_ref())?;
+        if user_profile.season_id != market_round.season_id { return Err(error!(BetError::MarketRoundNotOpen)); }
+        if user_profile.points < amount_arg { return Err(error!(BetError::InsufficientPoints)); }
+        user_profile.check_stake_limit(&ctx.accounts.config, amount_arg)?;
+
+        user_profile.points = user_profile.points.checked_sub(amount_arg).ok_or_else(|| error!(BetError::InsufficientPoints))?;
+        if direction_arg == Direction::Up {
+            market_round.total_up_staked = market_round.total_up_staked.checked_add(amount_arg).ok_or(BetError::PriceCalculationOverflow)?;
+        } else {
+            market_round.total_down_staked = market_round.total_down_staked.checked_add(amount_arg).ok_or(BetError::PriceCalculationOverflow)?;
+        }
+
+        // The round PDA is the mint authority for both outcome mints
+        let round_id_bytes = market_round.round_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[b"market_round".as_ref(), round_id_bytes.as_ref(), &[market_round.bump]]];
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.outcome_mint.to_account_info(),
+                    to: ctx.accounts.user_outcome_token_account.to_account_info(),
+                    authority: market_round.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount_arg,
+        )?;
+
+        msg!("Staked {} points {} in round {}. Points left: {}", amount_arg, direction_arg.label(), market_round.round_id, user_profile.points);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: -(amount_arg as i64), new_balance: user_profile.points, reason: PointsChangeReason::MarketStake });
+        if let Some(level) = user_profile.award_bet_xp(&ctx.accounts.config, amount_arg) {
+            emit_cpi!(LevelUp { user: user_profile.authority, level, xp: user_profile.xp });
+        }
+        Ok(())
+    }
+
+    pub fn resolve_market_round(ctx: Context<ResolveMarketRound>) -> Result<()> {
+        let market_round = &mut ctx.accounts.market_round;
+        let clock = Clock::get()?;
+
+        if market_round.status != MarketRoundStatus::Open { return Err(error!(BetError::MarketRoundAlreadyResolved)); }
+        if clock.unix_timestamp <= market_round.expiry_timestamp { return Err(error!(BetError::BetNotYetExpired)); }
+
+        let resolved_price = get_adjusted_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock)?;
+        market_round.resolved_price = resolved_price;
+        // Same rule as single bets: an unchanged price is a push and both sides get their stake back
+        market_round.status = if resolved_price > market_round.strike_price {
+            MarketRoundStatus::UpWon
+        } else if resolved_price < market_round.strike_price {
+            MarketRoundStatus::DownWon
+        } else {
+            MarketRoundStatus::Flat
+        };
+
+        msg!("Market round {} resolved. Strike: {}, Resolved: {}, Status: {:?}", market_round.round_id, market_round.strike_price, resolved_price, market_round.status);
+        Ok(())
+    }
+
+    pub fn redeem_outcome_tokens(ctx: Context<RedeemOutcomeTokens>, amount_arg: u64) -> Result<()> {
+        let market_round = &ctx.accounts.market_round;
+        let outcome_mint = ctx.accounts.outcome_mint.key();
+        let payout_per_token = match market_round.status {
+            MarketRoundStatus::Open => return Err(error!(BetError::MarketRoundNotResolved)),
+            MarketRoundStatus::UpWon if outcome_mint == market_round.up_mint => 2,
+            MarketRoundStatus::DownWon if outcome_mint == market_round.down_mint => 2,
+            MarketRoundStatus::Flat => 1, // Refund for both sides (the mint constraint already limits it to this round)
+            _ => return Err(error!(BetError::NotWinningOutcomeToken)),
+        };
+        if amount_arg == 0 { return Err(error!(BetError::ZeroAmount)); }
+
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.outcome_mint.to_account_info(),
+                    from: ctx.accounts.holder_token_account.to_account_info(),
+                    authority: ctx.accounts.holder.to_account_info(),
+                },
+            ),
+            amount_arg,
+        )?;
+
+        let holder_profile = &mut ctx.accounts.holder_profile;
+        let payout_amount = amount_arg.checked_mul(payout_per_token).ok_or(BetError::PriceCalculationOverflow)?;
+        let payout_amount = holder_profile.credit_season_points(market_round.season_id, payout_amount)?;
+        msg!("Redeemed {} outcome tokens of round {}. Payout: {}. New points: {}", amount_arg, market_round.round_id, payout_amount, holder_profile.points);
+        if payout_amount > 0 {
+            emit_cpi!(PointsChanged { user: holder_profile.authority, delta: payout_amount as i64, new_balance: holder_profile.points, reason: PointsChangeReason::MarketRedemption });
+        }
+        Ok(())
+    }
+
+    // --- Limit-Triggered Bets ---
+    // The stake (plus keeper tip) is reserved from the profile when the order is placed.
+    // Any keeper can open the bet once a fresh oracle price crosses the trigger level.
+    pub fn place_limit_order(ctx: Context<PlaceLimitOrder>, params: LimitOrderParams) -> Result<()> {
+        if params.asset_name != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
+        if params.amount == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if params.duration_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+        if params.trigger_price == 0 { return Err(error!(BetError::InvalidTriggerPrice)); }
+
+        let user_profile = &mut ctx.accounts.user_profile;
+        user_profile.require_current_season(load_season_config(&ctx.accounts.season_config)?.as_ref())?;
+        user_profile.check_stake_limit(&ctx.accounts.config, params.amount)?;
+        let total_reserved = params.amount.checked_add(params.keeper_tip).ok_or(BetError::PriceCalculationOverflow)?;
+        if user_profile.points < total_reserved { return Err(error!(BetError::InsufficientPoints)); }
+        user_profile.reserve_points(total_reserved)?;
+
+        let order = &mut ctx.accounts.limit_order;
+        order.user = ctx.accounts.user_signer.key();
+        order.order_id = params.order_id;
+        order.asset_name = params.asset_name;
+        order.trigger_price = params.trigger_price;
+        order.trigger_above = params.trigger_above;
+        order.direction = params.direction;
+        order.amount_reserved = params.amount;
+        order.keeper_tip = params.keeper_tip;
+        order.duration_seconds = params.duration_seconds;
+        order.created_at = Clock::get()?.unix_timestamp;
+        order.bump = ctx.bumps.limit_order;
+        order.version = LIMIT_ORDER_VERSION;
+        order.season_id = user_profile.season_id;
+
+        msg!("Limit order {} placed: {} {} points when price {} {}. Reserved: {}", order.order_id,
+            order.direction.label(), order.amount_reserved, if order.trigger_above {">="} else {"<="}, order.trigger_price, total_reserved);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: -(total_reserved as i64), new_balance: user_profile.points, reason: PointsChangeReason::OrderReserve });
+        Ok(())
+    }
+
+    pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>) -> Result<()> {
+        let order = &ctx.accounts.limit_order;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let refund = order.amount_reserved.checked_add(order.keeper_tip).ok_or(BetError::PriceCalculationOverflow)?;
+        user_profile.release_reserved_points(order.season_id, refund);
+        let refund = user_profile.credit_season_points(order.season_id, refund)?;
+        msg!("Limit order {} cancelled. Refunded: {}. Points: {}", order.order_id, refund, user_profile.points);
+        if refund > 0 {
+            emit_cpi!(PointsChanged { user: user_profile.authority, delta: refund as i64, new_balance: user_profile.points, reason: PointsChangeReason::OrderRefund });
+        }
+        Ok(())
+    }
+
+    pub fn execute_limit_order(ctx: Context<ExecuteLimitOrder>) -> Result<()> {
+        let order = &ctx.accounts.limit_order;
+        let clock = Clock::get()?;
+
+        let (current_price, publish_time) = read_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock, LIMIT_ORDER_MAX_PRICE_AGE_SECONDS)?;
+        // The proving price must have been published after the order was placed
+        if publish_time < order.created_at { return Err(error!(BetError::PythPriceTooOldOrUnavailable)); }
+        let triggered = if order.trigger_above { current_price >= order.trigger_price } else { current_price <= order.trigger_price };
+        if !triggered { return Err(error!(BetError::TriggerConditionNotMet)); }
+
+        let bet_index = ctx.accounts.user_profile.next_bet_index()?;
+        ctx.accounts.user_profile.release_reserved_points(order.season_id, order.amount_reserved.saturating_add(order.keeper_tip));
+        ctx.accounts.user_profile.record_bet_opened(order.amount_reserved, clock.unix_timestamp)?;
+        ctx.accounts.bet_account.open(order.user, bet_index, ctx.bumps.bet_account, ctx.accounts.keeper.key(), order.asset_name.clone(), current_price, clock.unix_timestamp, order.duration_seconds, order.direction, order.amount_reserved, BetKind::LimitOrder, order.season_id)?;
+
+        if let Some(level) = ctx.accounts.user_profile.award_bet_xp(&ctx.accounts.config, order.amount_reserved) {
+            emit_cpi!(LevelUp { user: order.user, level, xp: ctx.accounts.user_profile.xp });
+        }
+
+        let keeper_profile = &mut ctx.accounts.keeper_profile;
+        keeper_profile.points = keeper_profile.points.checked_add(order.keeper_tip).ok_or(BetError::PriceCalculationOverflow)?;
+
+        msg!("Limit order {} executed at price {} by keeper {}. Tip: {}", order.order_id, current_price, ctx.accounts.keeper.key(), order.keeper_tip);
+        emit_cpi!(BetOpened::from_bet(&ctx.accounts.bet_account));
+        emit_cpi!(PointsChanged { user: keeper_profile.authority, delta: order.keeper_tip as i64, new_balance: keeper_profile.points, reason: PointsChangeReason::KeeperTip });
+        Ok(())
+    }
+
+    // --- Scheduled Bets ---
+    // The stake is reserved when the bet is scheduled. Anyone can activate it once open_at has
+    // passed (within the activation window), using a price published at or after open_at.
+    pub fn schedule_bet(ctx: Context<ScheduleBet>, params: ScheduledBetParams) -> Result<()> {
+        let clock = Clock::get()?;
+        if params.asset_name != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
+        if params.amount == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if params.duration_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+        if params.open_at <= clock.unix_timestamp { return Err(error!(BetError::InvalidScheduleTime)); }
+
+        let user_profile = &mut ctx.accounts.user_profile;
+        user_profile.require_current_season(load_season_config(&ctx.accounts.season_config)?.as_ref())?;
+        if user_profile.points < params.amount { return Err(error!(BetError::InsufficientPoints)); }
+        user_profile.check_stake_limit(&ctx.accounts.config, params.amount)?;
+        user_profile.reserve_points(params.amount)?;
+
+        let scheduled_bet = &mut ctx.accounts.scheduled_bet;
+        scheduled_bet.user = ctx.accounts.user_signer.key();
+        scheduled_bet.schedule_id = params.schedule_id;
+        scheduled_bet.asset_name = params.asset_name;
+        scheduled_bet.open_at = params.open_at;
+        scheduled_bet.direction = params.direction;
+        scheduled_bet.amount_reserved = params.amount;
+        scheduled_bet.duration_seconds = params.duration_seconds;
+        scheduled_bet.bump = ctx.bumps.scheduled_bet;
+        scheduled_bet.version = SCHEDULED_BET_VERSION;
+        scheduled_bet.season_id = user_profile.season_id;
+
+        msg!("Bet {} scheduled to open at {}. Reserved: {}. Points left: {}", scheduled_bet.schedule_id, scheduled_bet.open_at, scheduled_bet.amount_reserved, user_profile.points);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: -(scheduled_bet.amount_reserved as i64), new_balance: user_profile.points, reason: PointsChangeReason::OrderReserve });
+        Ok(())
+    }
+
+    pub fn activate_scheduled_bet(ctx: Context<ActivateScheduledBet>) -> Result<()> {
+        let scheduled_bet = &ctx.accounts.scheduled_bet;
+        let clock = Clock::get()?;
+
+        if clock.unix_timestamp < scheduled_bet.open_at { return Err(error!(BetError::ScheduledBetNotYetOpen)); }
+        let activation_deadline = scheduled_bet.open_at.checked_add(SCHEDULED_BET_ACTIVATION_WINDOW_SECONDS).ok_or(BetError::TimestampOverflow)?;
+        if clock.unix_timestamp > activation_deadline { return Err(error!(BetError::ScheduledBetActivationWindowMissed)); }
+
+        let (strike_price, publish_time) = read_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock, SCHEDULED_BET_MAX_PRICE_AGE_SECONDS)?;
+        if publish_time < scheduled_bet.open_at { return Err(error!(BetError::PythPriceTooOldOrUnavailable)); }
+
+        let bet_index = ctx.accounts.user_profile.next_bet_index()?;
+        ctx.accounts.user_profile.release_reserved_points(scheduled_bet.season_id, scheduled_bet.amount_reserved);
+        ctx.accounts.user_profile.record_bet_opened(scheduled_bet.amount_reserved, clock.unix_timestamp)?;
+        ctx.accounts.bet_account.open(scheduled_bet.user, bet_index, ctx.bumps.bet_account, ctx.accounts.activator.key(), scheduled_bet.asset_name.clone(), strike_price, clock.unix_timestamp, scheduled_bet.duration_seconds, scheduled_bet.direction, scheduled_bet.amount_reserved, BetKind::Scheduled, scheduled_bet.season_id)?;
+
+        msg!("Scheduled bet {} activated at price {} by {}", scheduled_bet.schedule_id, strike_price, ctx.accounts.activator.key());
+        emit_cpi!(BetOpened::from_bet(&ctx.accounts.bet_account));
+        if let Some(level) = ctx.accounts.user_profile.award_bet_xp(&ctx.accounts.config, scheduled_bet.amount_reserved) {
+            emit_cpi!(LevelUp { user: scheduled_bet.user, level, xp: ctx.accounts.user_profile.xp });
+        }
+        Ok(())
+    }
+
+    pub fn cancel_scheduled_bet(ctx: Context<CancelScheduledBet>) -> Result<()> {
+        let scheduled_bet = &ctx.accounts.scheduled_bet;
+        let user_profile = &mut ctx.accounts.user_profile;
+        user_profile.release_reserved_points(scheduled_bet.season_id, scheduled_bet.amount_reserved);
+        let refund = user_profile.credit_season_points(scheduled_bet.season_id, scheduled_bet.amount_reserved)?;
+        msg!("Scheduled bet {} cancelled. Refunded: {}. Points: {}", scheduled_bet.schedule_id, refund, user_profile.points);
+        if refund > 0 {
+            emit_cpi!(PointsChanged { user: user_profile.authority, delta: refund as i64, new_balance: user_profile.points, reason: PointsChangeReason::OrderRefund });
+        }
+        Ok(())
+    }
+
+    // --- Auto-Rolling Recurring Bets ---
+    // A keeper cranks the subscription: it settles the iteration in flight (if expired) and opens
+    // the next one, until max_iterations, the stop-loss or the user's balance ends the run.
+    pub fn create_recurring_bet(ctx: Context<CreateRecurringBet>, params: RecurringBetParams) -> Result<()> {
+        if params.asset_name != "SOL/USD" { return Err(error!(BetError::UnsupportedAsset)); }
+        if params.stake_per_iteration == 0 { return Err(error!(BetError::ZeroAmount)); }
+        if params.duration_seconds <= 0 { return Err(error!(BetError::InvalidDuration)); }
+        if params.max_iterations == 0 { return Err(error!(BetError::InvalidMaxIterations)); }
+        ctx.accounts.user_profile.require_current_season(load_season_config(&ctx.accounts.season_config)?.as_ref())?;
+        ctx.accounts.user_profile.check_stake_limit(&ctx.accounts.config, params.stake_per_iteration)?;
+
+        let subscription = &mut ctx.accounts.recurring_bet;
+        subscription.user = ctx.accounts.user_signer.key();
+        subscription.subscription_id = params.subscription_id;
+        subscription.asset_name = params.asset_name;
+        subscription.stake_per_iteration = params.stake_per_iteration;
+        subscription.direction_rule = params.direction_rule;
+        subscription.next_direction = params.initial_direction;
+        subscription.duration_seconds = params.duration_seconds;
+        subscription.max_iterations = params.max_iterations;
+        subscription.iterations_opened = 0;
+        subscription.stop_loss = params.stop_loss;
+        subscription.cumulative_pnl = 0;
+        subscription.current_bet = Pubkey::default();
+        subscription.is_active = true;
+        subscription.bump = ctx.bumps.recurring_bet;
+        subscription.version = RECURRING_BET_VERSION;
+
+        msg!("Recurring bet {} created: {} points x {} iterations, rule {:?}", subscription.subscription_id, subscription.stake_per_iteration, subscription.max_iterations, subscription.direction_rule);
+        Ok(())
+    }
+
+    pub fn crank_recurring_bet(ctx: Context<CrankRecurringBet>) -> Result<()> {
+        let subscription_key = ctx.accounts.recurring_bet.key();
+        let subscription = &mut ctx.accounts.recurring_bet;
+        let user_profile = &mut ctx.accounts.user_profile;
+        let clock = Clock::get()?;
+        if !subscription.is_active { return Err(error!(BetError::RecurringBetInactive)); }
+
+        let current_price = get_adjusted_sol_usd_price(&ctx.accounts.pyth_price_feed, &clock)?;
+
+        // 1. Settle the iteration in flight
+        if subscription.current_bet != Pubkey::default() {
+            let previous_bet = ctx.accounts.previous_bet.as_mut().ok_or(BetError::RecurringBetPreviousBetMissing)?;
+            if previous_bet.key() != subscription.current_bet { return Err(error!(BetError::RecurringBetPreviousBetMissing)); }
+            let payout_amount = match previous_bet.status {
+                BetStatus::Active => {
+                    if clock.unix_timestamp <= previous_bet.expiry_timestamp { return Err(error!(BetError::BetNotYetExpired)); }
+                    let payout_amount = previous_bet.settle(current_price)?;
+                    let payout_amount = user_profile.credit_bet_payout(previous_bet, payout_amount)?;
+                    previous_bet.amount_credited = payout_amount;
+                    user_profile.record_bet_settled(previous_bet, payout_amount)?;
+                    if payout_amount > 0 {
+                        emit_cpi!(PointsChanged { user: user_profile.authority, delta: payout_amount as i64, new_balance: user_profile.points, reason: PointsChangeReason::BetPayout });
+                    }
+                    emit_cpi!(BetResolved::from_bet(previous_bet, payout_amount, ctx.accounts.keeper.key()));
+                    payout_amount
+                }
+                // Already settled elsewhere (resolve_bet, void_bet, cancel_bet, ...): use what was actually credited
+                _ => previous_bet.amount_credited,
+            };
+            let iteration_pnl = (payout_amount as i64).checked_sub(previous_bet.amount_staked as i64).ok_or(BetError::PriceCalculationOverflow)?;
+            subscription.cumulative_pnl = subscription.cumulative_pnl.checked_add(iteration_pnl).ok_or(BetError::PriceCalculationOverflow)?;
+            // Only an iteration settled against a price says anything about the market; voided or
+            // cancelled ones (resolved_price 0) keep the current direction.
+            let priced = matches!(previous_bet.status, BetStatus::Won | BetStatus::Lost | BetStatus::Push);
+            subscription.next_direction = match subscription.direction_rule {
+                DirectionRule::FollowLastOutcome if priced && previous_bet.resolved_price > previous_bet.initial_price => Direction::Up,
+                DirectionRule::FollowLastOutcome if priced && previous_bet.resolved_price < previous_bet.initial_price => Direction::Down,
+                DirectionRule::Alternate if priced => previous_bet.direction.opposite(),
+                _ => subscription.next_direction, // Fixed, a flat market or an unpriced settlement
+            };
+            subscription.current_bet = Pubkey::default();
+            previous_bet.recurring_bet = Pubkey::default();
+            msg!("Recurring bet {} iteration settled. Payout: {}. Cumulative P&L: {}", subscription.subscription_id, payout_amount, subscription.cumulative_pnl);
+            for leaderboard in [&mut ctx.accounts.points_leaderboard, &mut ctx.accounts.pnl_leaderboard] {
+                leaderboard.rerank(&[user_profile], clock.unix_timestamp);
+            }
+        }
+
+        // 2. Decide whether the run continues
+        let stop_loss_hit = subscription.stop_loss > 0 && subscription.cumulative_pnl <= -(subscription.stop_loss as i64);
+        let season_config = load_season_config(&ctx.accounts.season_config)?;
+        let stop_reason = if subscription.iterations_opened >= subscription.max_iterations {
+            Some("max iterations reached")
+        } else if user_profile.require_current_season(season_config.as_ref()).is_err() {
+            Some("season ended")
+        } else if stop_loss_hit {
+            Some("stop-loss hit")
+        } else if user_profile.points < subscription.stake_per_iteration {
+            Some("insufficient points")
+        } else if user_profile.check_stake_limit(&ctx.accounts.config, subscription.stake_per_iteration).is_err() {
+            Some("stake above level limit")
+        } else {
+            None
+        };
+        if let Some(reason) = stop_reason {
+            if ctx.accounts.next_bet.is_some() { return Err(error!(BetError::RecurringBetStopped)); }
+            subscription.is_active = false;
+            msg!("Recurring bet {} stopped: {}", subscription.subscription_id, reason);
+            return Ok(());
+        }
+
+        // 3. Open the next iteration
+        let next_bet = ctx.accounts.next_bet.as_mut().ok_or(BetError::RecurringBetNextBetMissing)?;
+        user_profile.points = user_profile.points.checked_sub(subscription.stake_per_iteration).ok_or_else(|| error!(BetError::InsufficientPoints))?;
+        let bet_index = user_profile.next_bet_index()?;
+        user_profile.record_bet_opened(subscription.stake_per_iteration, clock.unix_timestamp)?;
+        let next_bet_bump = ctx.bumps.next_bet.ok_or(BetError::RecurringBetNextBetMissing)?;
+        next_bet.open(subscription.user, bet_index, next_bet_bump, ctx.accounts.keeper.key(), subscription.asset_name.clone(), current_price, clock.unix_timestamp, subscription.duration_seconds, subscription.next_direction, subscription.stake_per_iteration, BetKind::Recurring, user_profile.season_id)?;
+        next_bet.recurring_bet = subscription_key;
+        subscription.current_bet = next_bet.key();
+        subscription.iterations_opened = subscription.iterations_opened.checked_add(1).ok_or(BetError::PriceCalculationOverflow)?;
+
+        msg!("Recurring bet {} iteration {} opened: {} {} points at {}", subscription.subscription_id, subscription.iterations_opened,
+            subscription.next_direction.label(), subscription.stake_per_iteration, current_price);
+        emit_cpi!(PointsChanged { user: user_profile.authority, delta: -(subscription.stake_per_iteration as i64), new_balance: user_profile.points, reason: PointsChangeReason::BetStake });
+        emit_cpi!(BetOpened::from_bet(next_bet));
+        if let Some(level) = user_profile.award_bet_xp(&ctx.accounts.config, subscription.stake_per_iteration) {
+            emit_cpi!(LevelUp { user: user_profile.authority, level, xp: user_profile.xp });
+        }
+        Ok(())
+    }
+
+    pub fn cancel_recurring_bet(ctx: Context<CancelRecurringBet>) -> Result<()> {
+        // An iteration still in flight stays a normal ActiveBet that the user can resolve themselves;
+        // it is released from the subscription so it can be closed once settled.
+        let subscription = &ctx.accounts.recurring_bet;
+        if subscription.current_bet != Pubkey::default() {
+            let current_bet = ctx.accounts.current_bet.as_mut().ok_or(BetError::RecurringBetPreviousBetMissing)?;
+            if current_bet.key() != subscription.current_bet { return Err(error!(BetError::RecurringBetPreviousBetMissing)); }
+            current_bet.recurring_bet = Pubkey::default();
+        }
+        msg!("Recurring bet {} cancelled after {} iterations", ctx.accounts.recurring_bet.subscription_id, ctx.accounts.recurring_bet.iterations_opened);
+        Ok(())
+    }
+
+    // Creates the per-user log that open_bet checks idempotency keys against
+    pub fn create_idempotency_log(ctx: Context<CreateIdempotencyLog>) -> Result<()> {
+        let idempotency_log = &mut ctx.accounts.idempotency_log;
+        idempotency_log.user = ctx.accounts.user_signer.key();
+        idempotency_log.entries = Vec::new();
+        idempotency_log.next_slot = 0;
+        idempotency_log.bump = ctx.bumps.idempotency_log;
+        idempotency_log.version = IDEMPOTENCY_LOG_VERSION;
+        msg!("Idempotency log created for {}", idempotency_log.user);
+        Ok(())
+    }
+
+    // --- Closing Settled Bets ---
+    // Settled bets can be closed by the bettor or the original rent payer. The rent always goes
+    // back to the rent payer; passing the user's bet history archives a compact summary first.
+    pub fn create_bet_history(ctx: Context<CreateBetHistory>) -> Result<()> {
+        let bet_history = &mut ctx.accounts.bet_history;
+        bet_history.user = ctx.accounts.user_signer.key();
+        bet_history.entries = Vec::new();
+        bet_history.next_slot = 0;
+        bet_history.total_archived = 0;
+        bet_history.bump = ctx.bumps.bet_history;
+        msg!("Bet history created for {}", bet_history.user);
+        Ok(())
+    }
+
+    pub fn close_bet(ctx: Context<CloseBet>) -> Result<()> {
+        let bet_account = &ctx.accounts.bet_account;
+        if let Some(bet_history) = ctx.accounts.bet_history.as_mut() {
+            bet_history.archive(bet_account)?;
+        }
+        msg!("Closed bet {} of {}. Rent returned to {}", bet_account.bet_index, bet_account.user, bet_account.rent_payer);
+        Ok(())
+    }
+
+    // remaining_accounts: [bet_account, rent_payer] pairs
+    pub fn close_bets<'info>(ctx: Context<'_, '_, 'info, 'info, CloseBets<'info>>) -> Result<()> {
+        let closer = ctx.accounts.closer.key();
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        if ctx.remaining_accounts.is_empty() || !pairs.remainder().is_empty() { return Err(error!(BetError::InvalidRemainingAccounts)); }
+
+        for pair in pairs {
+            let bet_account = Account::<ActiveBet>::try_from(&pair[0])?;
+            let rent_payer = &pair[1];
+            if bet_account.status == BetStatus::Active { return Err(error!(BetError::BetNotSettled)); }
+            if bet_account.recurring_bet != Pubkey::default() { return Err(error!(BetError::RecurringIterationPending)); }
+            if closer != bet_account.user && closer != bet_account.rent_payer { return Err(error!(BetError::UserProfileBetUserMismatch)); }
+            if rent_payer.key() != bet_account.rent_payer { return Err(error!(BetError::RentPayerMismatch)); }
+            if let Some(bet_history) = ctx.accounts.bet_history.as_mut() {
+                if bet_history.user != bet_account.user { return Err(error!(BetError::UserProfileBetUserMismatch)); }
+                bet_history.archive(&bet_account)?;
+            }
+            msg!("Closed bet {} of {}", bet_account.bet_index, bet_account.user);
+            bet_account.close(rent_payer.clone())?;
+        }
+        Ok(())
+    }
+
+    // --- Account Migration ---
+    // Upgrades a UserProfile, UserAuthState or ActiveBet written with an older layout to the current
+    // version in place. Anyone may pay for a migration; the account's contents are preserved.
+    pub fn migrate_account(ctx: Context<MigrateAccount>) -> Result<()> {
+        let account_info = ctx.accounts.account.to_account_info();
+        let payer = ctx.accounts.payer.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let discriminator = account_info.try_borrow_data()?.get(..DISCRIMINATOR_LENGTH).map(|d| d.to_vec()).ok_or(BetError::InvalidMigrationAccount)?;
+
+        let (account_type, old_version, new_version) = if discriminator == UserProfile::DISCRIMINATOR {
+            ("UserProfile", upgrade_account_layout::<UserProfile>(&account_info, &payer, &system_program)?, USER_PROFILE_VERSION)
+        } else if discriminator == UserAuthState::DISCRIMINATOR {
+            ("UserAuthState", upgrade_account_layout::<UserAuthState>(&account_info, &payer, &system_program)?, USER_AUTH_STATE_VERSION)
+        } else if discriminator == ActiveBet::DISCRIMINATOR {
+            ("ActiveBet", upgrade_account_layout::<ActiveBet>(&account_info, &payer, &system_program)?, ACTIVE_BET_VERSION)
+        } else if discriminator == ProgramConfig::DISCRIMINATOR {
+            ("ProgramConfig", upgrade_account_layout::<ProgramConfig>(&account_info, &payer, &system_program)?, PROGRAM_CONFIG_VERSION)
+        } else if discriminator == MarketRound::DISCRIMINATOR {
+            ("MarketRound", upgrade_account_layout::<MarketRound>(&account_info, &payer, &system_program)?, MARKET_ROUND_VERSION)
+        } else if discriminator == ReceiptListing::DISCRIMINATOR {
+            ("ReceiptListing", upgrade_account_layout::<ReceiptListing>(&account_info, &payer, &system_program)?, RECEIPT_LISTING_VERSION)
+        } else if discriminator == LimitOrder::DISCRIMINATOR {
+            ("LimitOrder", upgrade_account_layout::<LimitOrder>(&account_info, &payer, &system_program)?, LIMIT_ORDER_VERSION)
+        } else if discriminator == ScheduledBet::DISCRIMINATOR {
+            ("ScheduledBet", upgrade_account_layout::<ScheduledBet>(&account_info, &payer, &system_program)?, SCHEDULED_BET_VERSION)
+        } else if discriminator == RecurringBet::DISCRIMINATOR {
+            ("RecurringBet", upgrade_account_layout::<RecurringBet>(&account_info, &payer, &system_program)?, RECURRING_BET_VERSION)
+        } else if discriminator == IdempotencyLog::DISCRIMINATOR {
+            ("IdempotencyLog", upgrade_account_layout::<IdempotencyLog>(&account_info, &payer, &system_program)?, IDEMPOTENCY_LOG_VERSION)
+        } else {
+            return Err(error!(BetError::InvalidMigrationAccount));
+        };
+        msg!("{} {} migrated from version {} to {}", account_type, account_info.key(), old_version, new_version);
+        Ok(())
+    }
+
+    pub fn undelegate_from_magicblock(ctx: Context<UndelegateFromMagicBlock>) -> Result<()> {
+        msg!("Starting undelegation from MagicBlock...");
+    
+        // User authority must match the authority record IF we could read it.
+        // For now, we trust the client passed the correct user_auth_state_pda for their user.
+        // A more robust check would involve deriving the PDA here using ctx.accounts.user_authority.key()
+        // and ensuring it matches ctx.accounts.user_auth_state_to_undelegate.key().
+    
+        commit_and_undelegate_accounts(
+            &ctx.accounts.user_authority.to_account_info(), // Payer
+            vec![&ctx.accounts.user_auth_state_to_undelegate], // The AccountInfo of UserAuthState PDA
+            &ctx.accounts.magic_context.to_account_info(),
+            &ctx.accounts.magic_program.to_account_info(),
+        )?;
+    
+        // After this SDK call, ownership of user_auth_state_to_undelegate (the PDA)
+        // should be transferred BACK to this bs_bet program.
+        // Now, we need to reload it as Account<UserAuthState> to modify its data.
+        // This requires a CPI to our own program or a separate instruction.
+        // OR, the SDK itself might allow updating data during undelegation (unlikely).
+    
+        // For simplicity in the demo, we assume commit_and_undelegate_accounts ALSO
+        // allows us to update our internal flag, OR the client just knows.
+        // The most important thing is that MagicBlock no longer considers it delegated.
+        // To update is_delegated = false, we need another instruction AFTER ownership is back.
+    
+        // For the demo: this instruction primarily triggers MB undelegation.
+        // The client will then re-fetch UserAuthState (expecting ownership back)
+        // and then call manage_delegation(0,...) to set is_delegated=false. This is clunky.
+    
+        // Ideal: commit_and_undelegate_accounts SDK returns ownership, THEN we set is_delegated=false.
+        // If the SDK doesn't allow immediate modification after ownership transfer within the same IX,
+        // we need two steps on client:
+        // 1. Call this `undelegate_from_magicblock`.
+        // 2. Client then calls `manage_delegation(0, ...)` which can now access UserAuthState as Account<T>
+        //    and set is_delegated = false.
+    
+        msg!("MagicBlock SDK undelegation called. Ownership should be returned to this program.");
+        msg!("Client should now call manage_delegation(action=0) to finalize local state.");
+        emit_cpi!(Undelegated { user: ctx.accounts.user_authority.key(), auth_state: ctx.accounts.user_auth_state_to_undelegate.key() });
+        Ok(())
+    }
+
+}   
+
+// --- Accounts Structs ---
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateUserProfile<'info> {
+    #[account(init_if_needed, payer = user_authority, space = USER_PROFILE_SPACE, seeds = [b"profile".as_ref(), user_authority.key().as_ref()], bump)]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(init_if_needed, payer = user_authority, space = USER_AUTH_STATE_SPACE, seeds = [b"auth_state".as_ref(), user_authority.key().as_ref()], bump)]
+    pub user_auth_state_for_profile_creation: Account<'info, UserAuthState>,
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    // --- Referral: pass the referrer's profile to record it on a new profile ---
+    #[account(seeds = [b"profile".as_ref(), referrer_profile.authority.as_ref()], bump = referrer_profile.bump)]
+    pub referrer_profile: Option<Account<'info, UserProfile>>,
+    #[account(seeds = [b"season_config".as_ref()], bump = season_config.bump)]
+    pub season_config: Option<Account<'info, SeasonConfig>>, // Referral season; season 0 if seasons are not running
+}
+
+#[delegate] // MagicBlock SDK macro
+#[derive(Accounts)]
+pub struct DelegateAuthState<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: This is the UserAuthState PDA. MagicBlock's `del` attribute handles it.
+    #[account(mut, del)]
+    pub pda: AccountInfo<'info>, // This is the target
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(delegation_action: u8, user_signed_message: Vec<u8>, _signature: [u8; 64])]
+pub struct ManageDelegation<'info> {
+    #[account(init_if_needed, payer = user_authority, space = USER_AUTH_STATE_SPACE, seeds = [b"auth_state".as_ref(), user_authority.key().as_ref()], bump)]
+    pub user_auth_state: Account<'info, UserAuthState>,
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+    pub system_program: Program<'info, System>, // For init_if_needed
+    #[account(address = sysvar_instructions::ID)] // Still here if Ed25519 is re-enabled later
+    /// CHECK: Solana Instructions Sysvar.
+    pub ix_sysvar: AccountInfo<'info>,
+    // For undelegation (action == 0)
+    #[account(executable)]
+    /// CHECK: MagicBlock Program for undelegation.
+    pub magic_program: Option<AccountInfo<'info>>,
+    #[account(mut)]
+    /// CHECK: MagicBlock Context for undelegation.
+    pub magic_context: Option<AccountInfo<'info>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(asset_name_arg: String, direction_arg: Direction, amount_arg: u64, duration_seconds_arg: i64, user_authority_for_pdas: Pubkey, bet_index_arg: u64, expected_price_arg: Option<u64>, max_deviation_bps_arg: u16, idempotency_key_arg: Option<[u8; 16]>)]
+pub struct OpenBetAccounts<'info> {
+    #[account(
+        init,
+        payer = user_signer,
+        space = ACTIVE_BET_SPACE,
+        seeds = [b"bet".as_ref(), user_authority_for_pdas.as_ref(), bet_index_arg.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub bet_account: Account<'info, ActiveBet>,
+    #[account(mut)]
+    pub user_signer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = user_signer,
+        space = USER_AUTH_STATE_SPACE,
+        seeds = [b"auth_state".as_ref(), user_authority_for_pdas.as_ref()],
+        bump,
+        // --- THIS IS THE ONLY LINE THAT SHOULD BE DIFFERENT FROM YOUR LAST WORKING BUILD ---
+        // --- FOR THIS SPECIFIC STRUCT (OpenBetAccounts) ---
+        constraint = (
+            user_auth_state.is_delegated || // 1. MagicBlock "Quick Bet" mode
+            ( // 2. Standard user-signed bet (not delegated, UserAuthState owned by this program)
+                user_signer.key() == user_auth_state.user_authority && // Check: Signer is the PDA's authority
+                user_auth_state.user_authority != Pubkey::default()     // And PDA is already initialized
+            ) ||
+            user_auth_state.user_authority == Pubkey::default() || // 3. Initializing UserAuthState NOW
+            cfg!(feature = "test") // 4. Test mode
+        ) @ BetError::NotAuthenticatedOrDelegated
+    )]
+    pub user_auth_state: Account<'info, UserAuthState>, // Stays Account<T>
+    #[account(
+        init_if_needed, // removed `mut,`
+        payer = user_signer,
+        space = USER_PROFILE_SPACE,
+        seeds = [b"profile".as_ref(), user_authority_for_pdas.as_ref()],
+        bump,
+        constraint = user_profile.authority == user_authority_for_pdas || user_profile.authority == Pubkey::default() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    pub system_program: Program<'info, System>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    #[account(mut, seeds = [b"idempotency_log".as_ref(), user_authority_for_pdas.as_ref()], bump = idempotency_log.bump)]
+    pub idempotency_log: Option<Account<'info, IdempotencyLog>>, // Required when an idempotency key is passed
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    /// CHECK: UserSeasonRecord PDA of the current season; the handler checks the address and creates it on the first bet
+    #[account(mut)]
+    pub season_record: Option<UncheckedAccount<'info>>, // Required once seasons are running
+    // --- Optional receipt NFT: pass all of these to mint one for this bet ---
+    #[account(
+        init,
+        payer = user_signer,
+        seeds = [b"receipt".as_ref(), bet_account.key().as_ref()],
+        bump,
+        mint::decimals = RECEIPT_DECIMALS,
+        mint::authority = bet_account,
+        mint::token_program = token_program,
+    )]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        init,
+        payer = user_signer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = user_signer,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub token_program: Option<Program<'info, Token2022>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+}
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UndelegateFromMagicBlock<'info> {
+    #[account(mut)]
+    pub user_authority: Signer<'info>, // The user initiating undelegation
+
+    /// CHECK: This is the UserAuthState PDA currently owned by MagicBlock.
+    /// We pass it as AccountInfo because we can't load it as Account<UserAuthState> yet.
+    /// Its address is derived by the client using seeds [b"auth_state", user_authority.key()].
+    #[account(mut)] // Will be modified by MagicBlock (owner change)
+    pub user_auth_state_to_undelegate: AccountInfo<'info>,
+
+    // Accounts required by commit_and_undelegate_accounts
+    #[account(executable)]
+    /// CHECK: MagicBlock Program ID.
+    pub magic_program: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: MagicBlock Context Account.
+    pub magic_context: AccountInfo<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ResolveBetAccounts<'info> {
+    #[account(mut, constraint = bet_account.status == BetStatus::Active @ BetError::BetNotActiveOrAlreadyResolved)]
+    pub bet_account: Account<'info, ActiveBet>,
+    #[account(mut)]
+    pub resolver_signer: Signer<'info>, // Permissionless: anyone can resolve an expired bet
+    #[account(
+        seeds = [b"auth_state".as_ref(), bet_account.user.as_ref()],
+        bump = user_auth_state.bump
+    )]
+    pub user_auth_state: Account<'info, UserAuthState>, // Stays Account<T>
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), bet_account.user.as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == bet_account.user @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+    // --- Required only if the bet has a receipt NFT: the payout goes to the receipt holder ---
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Omitted while the bettor still holds the receipt. Must differ from user_profile: both copies are written back on exit
+    #[account(
+        mut,
+        constraint = bet_account.receipt_mint != Pubkey::default() @ BetError::UnexpectedReceiptAccounts,
+        constraint = receipt_holder_profile.key() != user_profile.key() @ BetError::DuplicateProfileAccount
+    )]
+    pub receipt_holder_profile: Option<Account<'info, UserProfile>>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    // --- Optional: a third-party resolver's profile, credited with the resolver tip ---
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), resolver_signer.key().as_ref()],
+        bump = resolver_profile.bump,
+        constraint = resolver_profile.authority == resolver_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub resolver_profile: Option<Account<'info, UserProfile>>,
+    // --- Leaderboards to re-rank the touched profiles on ---
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ResolveBets<'info> {
+    pub resolver_signer: Signer<'info>, // Permissionless: anyone can resolve expired bets
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    // --- Optional: the resolver's profile, credited with a tip for every other user's bet ---
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), resolver_signer.key().as_ref()],
+        bump = resolver_profile.bump,
+        constraint = resolver_profile.authority == resolver_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub resolver_profile: Option<Account<'info, UserProfile>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelBet<'info> {
+    #[account(
+        mut,
+        constraint = bet_account.status == BetStatus::Active @ BetError::BetNotActiveOrAlreadyResolved,
+        constraint = bet_account.user == user_signer.key() @ BetError::UserProfileBetUserMismatch
+    )]
+    pub bet_account: Account<'info, ActiveBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    pub user_signer: Signer<'info>,
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct VoidBet<'info> {
+    #[account(mut, constraint = bet_account.status == BetStatus::Active @ BetError::BetNotActiveOrAlreadyResolved)]
+    pub bet_account: Account<'info, ActiveBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), bet_account.user.as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == bet_account.user @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    pub voider: Signer<'info>, // Permissionless: anyone can void once the grace period has passed
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>, // The sponsored SOL/USD feed; must have no usable price
+    // --- Required only if the bet has a receipt NFT: the refund goes to the receipt holder ---
+    pub receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Omitted while the bettor still holds the receipt. Must differ from user_profile: both copies are written back on exit
+    #[account(
+        mut,
+        constraint = bet_account.receipt_mint != Pubkey::default() @ BetError::UnexpectedReceiptAccounts,
+        constraint = receipt_holder_profile.key() != user_profile.key() @ BetError::DuplicateProfileAccount
+    )]
+    pub receipt_holder_profile: Option<Account<'info, UserProfile>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(init, payer = admin, space = PROGRAM_CONFIG_SPACE, seeds = [b"config".as_ref()], bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ BetError::Unauthorized)]
+    pub program: Program<'info, crate::program::BsBet>,
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ BetError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminConfig<'info> {
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump, has_one = admin @ BetError::Unauthorized)]
+    pub config: Account<'info, ProgramConfig>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(achievement: Achievement)]
+pub struct MintAchievementBadge<'info> {
+    #[account(
+        seeds = [b"profile".as_ref(), user_authority.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_authority.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    /// CHECK: Badge mint PDA, created with the NonTransferable extension by the instruction.
+    #[account(mut, seeds = [b"badge".as_ref(), user_authority.key().as_ref(), &[achievement as u8]], bump)]
+    pub badge_mint: UncheckedAccount<'info>,
+    /// CHECK: The user's associated token account for the badge; the associated token program checks the address.
+    #[account(mut)]
+    pub badge_token_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: ProfileUpdateParams)]
+pub struct UpdateProfile<'info> {
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = PROFILE_METADATA_SPACE,
+        seeds = [b"profile_metadata".as_ref(), user_authority.key().as_ref()],
+        bump
+    )]
+    pub profile_metadata: Account<'info, ProfileMetadata>,
+    #[account(
+        seeds = [b"profile".as_ref(), user_authority.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_authority.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>, // Metadata can only be attached to an existing profile
+    // --- Name registry: only when the display name changes ---
+    #[account(
+        mut,
+        close = user_authority,
+        seeds = [b"name_registry".as_ref(), profile_metadata.display_name.as_bytes()],
+        bump = old_name_registry.bump,
+        constraint = old_name_registry.owner == user_authority.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub old_name_registry: Option<Account<'info, NameRegistry>>,
+    #[account(
+        init,
+        payer = user_authority,
+        space = NAME_REGISTRY_SPACE,
+        seeds = [b"name_registry".as_ref(), params.display_name.as_deref().unwrap_or_default().as_bytes()],
+        bump
+    )]
+    pub new_name_registry: Option<Account<'info, NameRegistry>>,
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimRefill<'info> {
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_authority.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_authority.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    pub user_authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SettleReferral<'info> {
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), referee_profile.authority.as_ref()],
+        bump = referee_profile.bump,
+        constraint = referee_profile.referrer != Pubkey::default() @ BetError::NoReferrer
+    )]
+    pub referee_profile: Account<'info, UserProfile>,
+    #[account(mut, seeds = [b"profile".as_ref(), referee_profile.referrer.as_ref()], bump = referrer_profile.bump)]
+    pub referrer_profile: Account<'info, UserProfile>,
+}
+
+#[derive(Accounts)]
+#[instruction(metric: LeaderboardMetric)]
+pub struct InitializeLeaderboard<'info> {
+    #[account(init, payer = admin, space = LEADERBOARD_SPACE, seeds = [b"leaderboard".as_ref(), &[metric as u8]], bump)]
+    pub leaderboard: Account<'info, Leaderboard>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump, has_one = admin @ BetError::Unauthorized)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeSeasons<'info> {
+    #[account(init, payer = admin, space = SEASON_CONFIG_SPACE, seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: Account<'info, SeasonConfig>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump, has_one = admin @ BetError::Unauthorized)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct JoinSeason<'info> {
+    #[account(seeds = [b"season_config".as_ref()], bump = season_config.bump)]
+    pub season_config: Account<'info, SeasonConfig>,
+    #[account(
+        init,
+        payer = user_authority,
+        space = USER_SEASON_RECORD_SPACE,
+        seeds = [b"season_record".as_ref(), user_authority.key().as_ref(), season_config.current_season_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub season_record: Account<'info, UserSeasonRecord>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_authority.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_authority.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EndSeason<'info> {
+    #[account(mut, seeds = [b"season_config".as_ref()], bump = season_config.bump)]
+    pub season_config: Account<'info, SeasonConfig>,
+    #[account(
+        init,
+        payer = payer,
+        space = SEASON_RESULT_SPACE,
+        seeds = [b"season_result".as_ref(), season_config.current_season_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub season_result: Account<'info, SeasonResult>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(merkle_root: [u8; 32], num_claims: u32)]
+pub struct CreateSeasonDistributor<'info> {
+    #[account(seeds = [b"season_result".as_ref(), season_result.season_id.to_le_bytes().as_ref()], bump = season_result.bump)]
+    pub season_result: Account<'info, SeasonResult>, // Rewards can only be posted for a finished season
+    #[account(
+        init,
+        payer = admin,
+        space = season_distributor_space(num_claims),
+        seeds = [b"season_distributor".as_ref(), season_result.season_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub distributor: Account<'info, SeasonDistributor>,
+    #[account(mint::token_program = token_program)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed, // The ATA address is public, so it may already have been created by someone else
+        payer = admin,
+        associated_token::mint = reward_mint,
+        associated_token::authority = distributor,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump, has_one = admin @ BetError::Unauthorized)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>, // Rewards may be classic SPL or Token-2022 mints
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimSeasonReward<'info> {
+    #[account(mut, seeds = [b"season_distributor".as_ref(), distributor.season_id.to_le_bytes().as_ref()], bump = distributor.bump)]
+    pub distributor: Account<'info, SeasonDistributor>,
+    #[account(address = distributor.reward_mint @ BetError::InvalidRewardMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, associated_token::mint = reward_mint, associated_token::authority = distributor, associated_token::token_program = token_program)]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = reward_mint,
+        associated_token::authority = claimant,
+        associated_token::token_program = token_program,
+    )]
+    pub claimant_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [b"profile".as_ref(), claimant.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == claimant.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>, // The merkle leaf commits to this PDA
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ListBetReceipt<'info> {
+    #[account(constraint = bet_account.receipt_mint == receipt_mint.key() @ BetError::InvalidReceiptTokenAccount)]
+    pub bet_account: Account<'info, ActiveBet>,
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = receipt_mint, token::authority = seller, token::token_program = token_program)]
+    pub seller_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(init, payer = seller, space = RECEIPT_LISTING_SPACE, seeds = [b"listing".as_ref(), receipt_mint.key().as_ref()], bump)]
+    pub listing: Account<'info, ReceiptListing>,
+    #[account(
+        init_if_needed, // The ATA address is public, so it may already have been created by someone else
+        payer = seller,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = listing,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct BuyBetReceipt<'info> {
+    #[account(mut, close = seller, seeds = [b"listing".as_ref(), receipt_mint.key().as_ref()], bump = listing.bump)]
+    pub listing: Account<'info, ReceiptListing>,
+    #[account(address = listing.bet @ BetError::InvalidReceiptTokenAccount)]
+    pub bet_account: Account<'info, ActiveBet>,
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, associated_token::mint = receipt_mint, associated_token::authority = listing, associated_token::token_program = token_program)]
+    pub escrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), buyer.key().as_ref()],
+        bump = buyer_profile.bump,
+        constraint = buyer_profile.authority == buyer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub buyer_profile: Account<'info, UserProfile>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), listing.seller.as_ref()],
+        bump = seller_profile.bump,
+        constraint = seller_profile.authority == listing.seller @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub seller_profile: Account<'info, UserProfile>,
+    // Buying one's own listing would load the same profile as buyer and seller
+    #[account(mut, constraint = buyer.key() != listing.seller @ BetError::DuplicateProfileAccount)]
+    pub buyer: Signer<'info>,
+    /// CHECK: Receives the listing and escrow rent back. Checked against listing.seller.
+    #[account(mut, address = listing.seller @ BetError::UserProfileAuthorityMismatch)]
+    pub seller: AccountInfo<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelReceiptListing<'info> {
+    #[account(
+        mut,
+        close = seller,
+        seeds = [b"listing".as_ref(), receipt_mint.key().as_ref()],
+        bump = listing.bump,
+        has_one = seller @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub listing: Account<'info, ReceiptListing>,
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, associated_token::mint = receipt_mint, associated_token::authority = listing, associated_token::token_program = token_program)]
+    pub escrow_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, token::mint = receipt_mint, token::authority = seller, token::token_program = token_program)]
+    pub seller_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+}
+
+#[derive(Accounts)]
+#[instruction(round_id: u64)]
+pub struct InitializeMarketRound<'info> {
+    #[account(init, payer = payer, space = MARKET_ROUND_SPACE, seeds = [b"market_round".as_ref(), round_id.to_le_bytes().as_ref()], bump)]
+    pub market_round: Account<'info, MarketRound>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"up_mint".as_ref(), market_round.key().as_ref()],
+        bump,
+        mint::decimals = OUTCOME_TOKEN_DECIMALS,
+        mint::authority = market_round,
+        mint::token_program = token_program,
+    )]
+    pub up_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"down_mint".as_ref(), market_round.key().as_ref()],
+        bump,
+        mint::decimals = OUTCOME_TOKEN_DECIMALS,
+        mint::authority = market_round,
+        mint::token_program = token_program,
+    )]
+    pub down_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Staking window and longest round duration
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(direction_arg: Direction, amount_arg: u64)]
+pub struct StakeMarketRound<'info> {
+    #[account(mut, seeds = [b"market_round".as_ref(), market_round.round_id.to_le_bytes().as_ref()], bump = market_round.bump)]
+    pub market_round: Account<'info, MarketRound>,
+    #[account(
+        mut,
+        constraint = outcome_mint.key() == (if direction_arg == Direction::Up { market_round.up_mint } else { market_round.down_mint }) @ BetError::InvalidOutcomeMint
+    )]
+    pub outcome_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = user_signer,
+        associated_token::mint = outcome_mint,
+        associated_token::authority = user_signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_outcome_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(mut)]
+    pub user_signer: Signer<'info>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveMarketRound<'info> {
+    #[account(mut, seeds = [b"market_round".as_ref(), market_round.round_id.to_le_bytes().as_ref()], bump = market_round.bump)]
+    pub market_round: Account<'info, MarketRound>,
+    pub resolver: Signer<'info>, // Permissionless: anyone can resolve an expired round
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RedeemOutcomeTokens<'info> {
+    #[account(seeds = [b"market_round".as_ref(), market_round.round_id.to_le_bytes().as_ref()], bump = market_round.bump)]
+    pub market_round: Account<'info, MarketRound>,
+    #[account(
+        mut,
+        constraint = (outcome_mint.key() == market_round.up_mint || outcome_mint.key() == market_round.down_mint) @ BetError::InvalidOutcomeMint
+    )]
+    pub outcome_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = outcome_mint, token::authority = holder, token::token_program = token_program)]
+    pub holder_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), holder.key().as_ref()],
+        bump = holder_profile.bump,
+        constraint = holder_profile.authority == holder.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub holder_profile: Account<'info, UserProfile>, // Whoever holds the tokens gets the points
+    pub holder: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(params: LimitOrderParams)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(
+        init,
+        payer = user_signer,
+        space = LIMIT_ORDER_SPACE,
+        seeds = [b"limit_order".as_ref(), user_signer.key().as_ref(), params.order_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub limit_order: Account<'info, LimitOrder>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+    #[account(mut)]
+    pub user_signer: Signer<'info>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    pub system_program: Program<'info, System>,
+}
 
-// --- Accounts Structs ---
+#[event_cpi]
 #[derive(Accounts)]
-pub struct CreateUserProfile<'info> {
-    #[account(init_if_needed, payer = user_authority, space = 8 + USER_PROFILE_SPACE, seeds = [b"profile".as_ref(), user_authority.key().as_ref()], bump)]
+pub struct CancelLimitOrder<'info> {
+    #[account(
+        mut,
+        close = user_signer,
+        seeds = [b"limit_order".as_ref(), user_signer.key().as_ref(), limit_order.order_id.to_le_bytes().as_ref()],
+        bump = limit_order.bump,
+        constraint = limit_order.user == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub limit_order: Account<'info, LimitOrder>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
     pub user_profile: Account<'info, UserProfile>,
-    #[account(init_if_needed, payer = user_authority, space = 8 + USER_AUTH_STATE_SPACE, seeds = [b"auth_state".as_ref(), user_authority.key().as_ref()], bump)]
-    pub user_auth_state_for_profile_creation: Account<'info, UserAuthState>,
     #[account(mut)]
-    pub user_authority: Signer<'info>,
+    pub user_signer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteLimitOrder<'info> {
+    #[account(
+        mut,
+        close = order_owner, // Rent goes back to the user who placed the order
+        seeds = [b"limit_order".as_ref(), limit_order.user.as_ref(), limit_order.order_id.to_le_bytes().as_ref()],
+        bump = limit_order.bump
+    )]
+    pub limit_order: Account<'info, LimitOrder>,
+    /// CHECK: Only receives the order's rent. Checked against limit_order.user.
+    #[account(mut, address = limit_order.user @ BetError::UserProfileBetUserMismatch)]
+    pub order_owner: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), limit_order.user.as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == limit_order.user @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>, // Owns the bet counter for the new bet PDA
+    #[account(
+        init,
+        payer = keeper,
+        space = ACTIVE_BET_SPACE,
+        seeds = [b"bet".as_ref(), limit_order.user.as_ref(), user_profile.bet_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub bet_account: Account<'info, ActiveBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), keeper.key().as_ref()],
+        bump = keeper_profile.bump,
+        constraint = keeper_profile.authority == keeper.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub keeper_profile: Account<'info, UserProfile>, // Receives the keeper tip
+    // The owner executing their own order would load their profile twice; cancel and open_bet instead
+    #[account(mut, constraint = keeper.key() != limit_order.user @ BetError::DuplicateProfileAccount)]
+    pub keeper: Signer<'info>,
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
     pub system_program: Program<'info, System>,
 }
 
-#[delegate] // MagicBlock SDK macro
+#[event_cpi]
 #[derive(Accounts)]
-pub struct DelegateAuthState<'info> {
+#[instruction(params: ScheduledBetParams)]
+pub struct ScheduleBet<'info> {
+    #[account(
+        init,
+        payer = user_signer,
+        space = SCHEDULED_BET_SPACE,
+        seeds = [b"scheduled_bet".as_ref(), user_signer.key().as_ref(), params.schedule_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub scheduled_bet: Account<'info, ScheduledBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
     #[account(mut)]
-    pub payer: Signer<'info>,
-    /// CHECK: This is the UserAuthState PDA. MagicBlock's `del` attribute handles it.
-    #[account(mut, del)]
-    pub pda: AccountInfo<'info>, // This is the target
+    pub user_signer: Signer<'info>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-#[instruction(delegation_action: u8, user_signed_message: Vec<u8>, _signature: [u8; 64])]
-pub struct ManageDelegation<'info> {
-    #[account(init_if_needed, payer = user_authority, space = 8 + USER_AUTH_STATE_SPACE, seeds = [b"auth_state".as_ref(), user_authority.key().as_ref()], bump)]
-    pub user_auth_state: Account<'info, UserAuthState>,
-    #[account(mut)]
-    pub user_authority: Signer<'info>,
-    pub system_program: Program<'info, System>, // For init_if_needed
-    #[account(address = sysvar_instructions::ID)] // Still here if Ed25519 is re-enabled later
-    /// CHECK: Solana Instructions Sysvar.
-    pub ix_sysvar: AccountInfo<'info>,
-    // For undelegation (action == 0)
-    #[account(executable)]
-    /// CHECK: MagicBlock Program for undelegation.
-    pub magic_program: Option<AccountInfo<'info>>,
+pub struct ActivateScheduledBet<'info> {
+    #[account(
+        mut,
+        close = bet_owner, // Rent goes back to the user who scheduled the bet
+        seeds = [b"scheduled_bet".as_ref(), scheduled_bet.user.as_ref(), scheduled_bet.schedule_id.to_le_bytes().as_ref()],
+        bump = scheduled_bet.bump
+    )]
+    pub scheduled_bet: Account<'info, ScheduledBet>,
+    /// CHECK: Only receives the scheduled bet's rent. Checked against scheduled_bet.user.
+    #[account(mut, address = scheduled_bet.user @ BetError::UserProfileBetUserMismatch)]
+    pub bet_owner: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), scheduled_bet.user.as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == scheduled_bet.user @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>, // Owns the bet counter for the new bet PDA
+    #[account(
+        init,
+        payer = activator,
+        space = ACTIVE_BET_SPACE,
+        seeds = [b"bet".as_ref(), scheduled_bet.user.as_ref(), user_profile.bet_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub bet_account: Account<'info, ActiveBet>,
     #[account(mut)]
-    /// CHECK: MagicBlock Context for undelegation.
-    pub magic_context: Option<AccountInfo<'info>>,
+    pub activator: Signer<'info>, // Permissionless: anyone can activate once open_at has passed
+    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-#[instruction(asset_name_arg: String, direction_arg: u8, amount_arg: u64, duration_seconds_arg: i64, user_authority_for_pdas: Pubkey)]
-pub struct OpenBetAccounts<'info> {
-    #[account(init, payer = user_signer, space = 8 + ACTIVE_BET_SPACE)]
-    pub bet_account: Account<'info, ActiveBet>,
+pub struct CancelScheduledBet<'info> {
+    #[account(
+        mut,
+        close = user_signer,
+        seeds = [b"scheduled_bet".as_ref(), user_signer.key().as_ref(), scheduled_bet.schedule_id.to_le_bytes().as_ref()],
+        bump = scheduled_bet.bump,
+        constraint = scheduled_bet.user == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub scheduled_bet: Account<'info, ScheduledBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>,
     #[account(mut)]
     pub user_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: RecurringBetParams)]
+pub struct CreateRecurringBet<'info> {
     #[account(
-        init_if_needed,
+        init,
         payer = user_signer,
-        space = 8 + USER_AUTH_STATE_SPACE,
-        seeds = [b"auth_state".as_ref(), user_authority_for_pdas.as_ref()],
-        bump,
-        // --- THIS IS THE ONLY LINE THAT SHOULD BE DIFFERENT FROM YOUR LAST WORKING BUILD ---
-        // --- FOR THIS SPECIFIC STRUCT (OpenBetAccounts) ---
-        constraint = (
-            user_auth_state.is_delegated == true || // 1. MagicBlock "Quick Bet" mode
-            ( // 2. Standard user-signed bet (UserAuthState owned by this program)
-                user_signer.key() == user_auth_state.user_authority && // Check: Signer is the PDA's authority
-                user_auth_state.is_delegated == false &&                // And not in MB delegated mode
-                user_auth_state.user_authority != Pubkey::default()     // And PDA is already initialized
-            ) ||
-            user_auth_state.user_authority == Pubkey::default() || // 3. Initializing UserAuthState NOW
-            cfg!(feature = "test") // 4. Test mode
-        ) @ BetError::NotAuthenticatedOrDelegated
+        space = RECURRING_BET_SPACE,
+        seeds = [b"recurring_bet".as_ref(), user_signer.key().as_ref(), params.subscription_id.to_le_bytes().as_ref()],
+        bump
     )]
-    pub user_auth_state: Account<'info, UserAuthState>, // Stays Account<T>
+    pub recurring_bet: Account<'info, RecurringBet>,
     #[account(
-        init_if_needed, // removed `mut,`
-        payer = user_signer,
-        space = 8 + USER_PROFILE_SPACE,
-        seeds = [b"profile".as_ref(), user_authority_for_pdas.as_ref()],
-        bump,
-        constraint = user_profile.authority == user_authority_for_pdas || user_profile.authority == Pubkey::default() @ BetError::UserProfileAuthorityMismatch
+        seeds = [b"profile".as_ref(), user_signer.key().as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub user_profile: Account<'info, UserProfile>, // Must exist before subscribing
+    #[account(mut)]
+    pub user_signer: Signer<'info>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CrankRecurringBet<'info> {
+    #[account(
+        mut,
+        seeds = [b"recurring_bet".as_ref(), recurring_bet.user.as_ref(), recurring_bet.subscription_id.to_le_bytes().as_ref()],
+        bump = recurring_bet.bump
+    )]
+    pub recurring_bet: Account<'info, RecurringBet>,
+    #[account(
+        mut,
+        seeds = [b"profile".as_ref(), recurring_bet.user.as_ref()],
+        bump = user_profile.bump,
+        constraint = user_profile.authority == recurring_bet.user @ BetError::UserProfileAuthorityMismatch
     )]
     pub user_profile: Account<'info, UserProfile>,
+    #[account(mut)]
+    pub previous_bet: Option<Account<'info, ActiveBet>>, // Required while an iteration is in flight
+    #[account(
+        init,
+        payer = keeper,
+        space = ACTIVE_BET_SPACE,
+        seeds = [b"bet".as_ref(), recurring_bet.user.as_ref(), user_profile.bet_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub next_bet: Option<Account<'info, ActiveBet>>,     // Required unless the run stops
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::Points as u8]], bump = points_leaderboard.bump)]
+    pub points_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut, seeds = [b"leaderboard".as_ref(), &[LeaderboardMetric::NetPnl as u8]], bump = pnl_leaderboard.bump)]
+    pub pnl_leaderboard: Account<'info, Leaderboard>,
+    #[account(mut)]
+    pub keeper: Signer<'info>,
     pub pyth_price_feed: Account<'info, PriceUpdateV2>,
+    /// CHECK: SeasonConfig PDA; may not exist yet. Seasons are enforced once it does (see load_season_config)
+    #[account(seeds = [b"season_config".as_ref()], bump)]
+    pub season_config: UncheckedAccount<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, ProgramConfig>, // Level thresholds and stake caps
     pub system_program: Program<'info, System>,
 }
+
 #[derive(Accounts)]
-pub struct UndelegateFromMagicBlock<'info> {
+pub struct CancelRecurringBet<'info> {
+    #[account(
+        mut,
+        close = user_signer,
+        seeds = [b"recurring_bet".as_ref(), user_signer.key().as_ref(), recurring_bet.subscription_id.to_le_bytes().as_ref()],
+        bump = recurring_bet.bump,
+        constraint = recurring_bet.user == user_signer.key() @ BetError::UserProfileAuthorityMismatch
+    )]
+    pub recurring_bet: Account<'info, RecurringBet>,
     #[account(mut)]
-    pub user_authority: Signer<'info>, // The user initiating undelegation
-
-    /// CHECK: This is the UserAuthState PDA currently owned by MagicBlock.
-    /// We pass it as AccountInfo because we can't load it as Account<UserAuthState> yet.
-    /// Its address is derived by the client using seeds [b"auth_state", user_authority.key()].
-    #[account(mut)] // Will be modified by MagicBlock (owner change)
-    pub user_auth_state_to_undelegate: AccountInfo<'info>,
+    pub current_bet: Option<Account<'info, ActiveBet>>, // Required while an iteration is in flight
+    #[account(mut)]
+    pub user_signer: Signer<'info>,
+}
 
-    // Accounts required by commit_and_undelegate_accounts
-    #[account(executable)]
-    /// CHECK: MagicBlock Program ID.
-    pub magic_program: AccountInfo<'info>,
+#[derive(Accounts)]
+pub struct CreateIdempotencyLog<'info> {
+    #[account(init, payer = user_signer, space = IDEMPOTENCY_LOG_SPACE, seeds = [b"idempotency_log".as_ref(), user_signer.key().as_ref()], bump)]
+    pub idempotency_log: Account<'info, IdempotencyLog>,
     #[account(mut)]
-    /// CHECK: MagicBlock Context Account.
-    pub magic_context: AccountInfo<'info>,
+    pub user_signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ResolveBetAccounts<'info> {
-    #[account(mut, constraint = bet_account.user == resolver_signer.key() @ BetError::UserProfileBetUserMismatch, constraint = bet_account.status == 0 @ BetError::BetNotActiveOrAlreadyResolved)]
-    pub bet_account: Account<'info, ActiveBet>,
+pub struct CreateBetHistory<'info> {
+    #[account(init, payer = user_signer, space = USER_BET_HISTORY_SPACE, seeds = [b"bet_history".as_ref(), user_signer.key().as_ref()], bump)]
+    pub bet_history: Account<'info, UserBetHistory>,
     #[account(mut)]
-    pub resolver_signer: Signer<'info>,
-    #[account(
-        seeds = [b"auth_state".as_ref(), resolver_signer.key().as_ref()],
-        bump = user_auth_state.bump,
-        // --- THIS IS THE ONLY LINE THAT SHOULD BE DIFFERENT FROM YOUR LAST WORKING BUILD ---
-        // --- FOR THIS SPECIFIC STRUCT (ResolveBetAccounts) ---
-        constraint = (
-            user_auth_state.is_delegated == true ||
-            (
-                resolver_signer.key() == user_auth_state.user_authority && // Check: Signer is the PDA's authority
-                user_auth_state.is_delegated == false &&
-                user_auth_state.user_authority != Pubkey::default()
-            ) ||
-            cfg!(feature = "test")
-        ) @ BetError::NotAuthenticatedOrDelegated
-    )]
-    pub user_auth_state: Account<'info, UserAuthState>, // Stays Account<T>
+    pub user_signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBet<'info> {
     #[account(
         mut,
-        seeds = [b"profile".as_ref(), resolver_signer.key().as_ref()],
-        bump = user_profile.bump,
-        constraint = user_profile.authority == resolver_signer.key() @ BetError::UserProfileAuthorityMismatch
+        close = rent_payer,
+        constraint = bet_account.status != BetStatus::Active @ BetError::BetNotSettled,
+        constraint = bet_account.recurring_bet == Pubkey::default() @ BetError::RecurringIterationPending,
+        constraint = (closer.key() == bet_account.user || closer.key() == bet_account.rent_payer) @ BetError::UserProfileBetUserMismatch
     )]
-    pub user_profile: Account<'info, UserProfile>,
-    pub pyth_price_feed: Account<'info, PriceUpdateV2>,
-    pub clock: Sysvar<'info, Clock>,
+    pub bet_account: Account<'info, ActiveBet>,
+    /// CHECK: Receives the bet's rent. Checked against bet_account.rent_payer.
+    #[account(mut, address = bet_account.rent_payer @ BetError::RentPayerMismatch)]
+    pub rent_payer: AccountInfo<'info>,
+    #[account(mut, seeds = [b"bet_history".as_ref(), bet_account.user.as_ref()], bump = bet_history.bump)]
+    pub bet_history: Option<Account<'info, UserBetHistory>>, // Optional: archive a summary before closing
+    pub closer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseBets<'info> {
+    #[account(mut, seeds = [b"bet_history".as_ref(), bet_history.user.as_ref()], bump = bet_history.bump)]
+    pub bet_history: Option<Account<'info, UserBetHistory>>, // Optional: archive summaries before closing
+    pub closer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    /// CHECK: May be too short to deserialize with the current layout; the discriminator is checked in the handler.
+    #[account(mut, owner = crate::ID @ BetError::InvalidMigrationAccount)]
+    pub account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
@@ -494,11 +3886,411 @@ pub enum BetError {
     #[msg("User does not have enough points for this bet.")] InsufficientPoints,
     #[msg("The user profile's authority does not match the signer.")] UserProfileAuthorityMismatch,
     #[msg("The user profile does not belong to the user who placed the bet.")] UserProfileBetUserMismatch,
-    #[msg("Bet direction must be 0 (DOWN) or 1 (UP).")] InvalidDirection,
+    // Deprecated: Direction is a typed enum now. Kept so later error codes stay stable.
+    #[msg("Deprecated: bet direction must be 0 (DOWN) or 1 (UP).")] InvalidDirection,
     #[msg("Bet amount must be greater than zero.")] ZeroAmount,
     #[msg("Bet duration must be positive.")] InvalidDuration,
     #[msg("User is not properly authenticated or state not delegated for this action.")] NotAuthenticatedOrDelegated,
     #[msg("User authentication state is already prepared for MagicBlock delegation or fully delegated.")] AlreadyDelegated,
     #[msg("User authentication state is not currently in a MagicBlock delegated state.")] NotDelegated,
     #[msg("Invalid authentication signature or message provided for delegation.")] InvalidDelegationSignature,
-}
\ No newline at end of file
+    #[msg("Market round is not open for staking.")] MarketRoundNotOpen,
+    #[msg("Market round has already been resolved.")] MarketRoundAlreadyResolved,
+    #[msg("Market round has not been resolved yet.")] MarketRoundNotResolved,
+    #[msg("Outcome mint does not belong to this market round or direction.")] InvalidOutcomeMint,
+    #[msg("Only tokens of the winning outcome can be redeemed.")] NotWinningOutcomeToken,
+    #[msg("This bet has a receipt NFT: the receipt token account (and the holder's profile if it is not the bettor) are required.")] ReceiptAccountsMissing,
+    #[msg("Receipt token account does not hold this bet's receipt NFT.")] InvalidReceiptTokenAccount,
+    #[msg("Receipt holder profile does not belong to the receipt token account owner.")] ReceiptHolderProfileMismatch,
+    #[msg("Receipts can only be traded while the bet is active and not yet expired.")] ReceiptListingExpired,
+    #[msg("Trigger price must be greater than zero.")] InvalidTriggerPrice,
+    #[msg("Oracle price has not crossed the order's trigger level.")] TriggerConditionNotMet,
+    #[msg("Scheduled open time must be in the future.")] InvalidScheduleTime,
+    #[msg("Scheduled bet cannot be activated before its open time.")] ScheduledBetNotYetOpen,
+    #[msg("Scheduled bet activation window has passed; cancel it to reclaim the stake.")] ScheduledBetActivationWindowMissed,
+    // Deprecated: DirectionRule is a typed enum now. Kept so later error codes stay stable.
+    #[msg("Deprecated: direction rule must be 0 (Fixed), 1 (Follow last outcome) or 2 (Alternate).")] InvalidDirectionRule,
+    #[msg("Max iterations must be greater than zero.")] InvalidMaxIterations,
+    #[msg("Recurring bet is no longer active.")] RecurringBetInactive,
+    #[msg("The recurring bet's in-flight iteration must be passed as previous_bet.")] RecurringBetPreviousBetMissing,
+    #[msg("A new bet account must be passed as next_bet to open the next iteration.")] RecurringBetNextBetMissing,
+    #[msg("Recurring bet stops on this crank; do not pass next_bet.")] RecurringBetStopped,
+    #[msg("Bet index does not match the profile's next bet index.")] BetIndexMismatch,
+    #[msg("Only settled bets can be closed.")] BetNotSettled,
+    #[msg("Rent payer account does not match the bet's rent payer.")] RentPayerMismatch,
+    #[msg("Remaining accounts are missing or not in the expected groups.")] InvalidRemainingAccounts,
+    #[msg("Too many bets in one batch.")] TooManyBetsInBatch,
+    #[msg("Signer is not authorized to perform this action.")] Unauthorized,
+    #[msg("The same profile account was passed in more than one role.")] DuplicateProfileAccount,
+    #[msg("Bet can only be voided once the grace period after expiry has passed.")] VoidGracePeriodNotElapsed,
+    #[msg("Basis points value cannot exceed 10000.")] InvalidBasisPoints,
+    #[msg("The cancellation window for this bet has elapsed.")] CancelWindowElapsed,
+    #[msg("The oracle price has moved too far since the bet was opened to cancel it.")] PriceMovedTooFarToCancel,
+    #[msg("Bets with a receipt NFT cannot be cancelled.")] ReceiptBetNotCancellable,
+    #[msg("Oracle price moved beyond the allowed deviation from the expected price.")] SlippageExceeded,
+    #[msg("An idempotency key was supplied without the user's idempotency log account.")] IdempotencyLogMissing,
+    #[msg("A bet was already opened with this idempotency key.")] DuplicateIdempotencyKey,
+    #[msg("Account is not a program account of the expected type.")] InvalidMigrationAccount,
+    #[msg("Account was written by a newer program version.")] UnsupportedAccountVersion,
+    #[msg("The user's season record must be passed while seasons are running.")] SeasonRecordMissing,
+    #[msg("The current season has not reached its end time yet.")] SeasonNotOver,
+    #[msg("Distributor must have between 1 and MAX_DISTRIBUTOR_CLAIMS claims.")] InvalidDistributorSize,
+    #[msg("Claim index is outside the distributor's range.")] ClaimIndexOutOfRange,
+    #[msg("This season reward has already been claimed.")] RewardAlreadyClaimed,
+    #[msg("Merkle proof does not match the distributor's root.")] InvalidMerkleProof,
+    #[msg("Reward mint does not match the distributor.")] InvalidRewardMint,
+    #[msg("Balance is not below the refill floor.")] NotEligibleForRefill,
+    #[msg("A refill was already claimed within the cooldown period.")] RefillOnCooldown,
+    #[msg("A referrer can only be set when the profile is first created.")] ReferralOnExistingProfile,
+    #[msg("Users cannot refer themselves.")] SelfReferral,
+    #[msg("Referral would create a cycle.")] ReferralCycle,
+    #[msg("Profile was not referred by anyone.")] NoReferrer,
+    #[msg("No referral bonus or share is due.")] NothingToSettle,
+    #[msg("Display name must be 3-20 characters of a-z, 0-9 or _.")] InvalidDisplayName,
+    #[msg("Avatar URI must be an https://, ipfs:// or ar:// URI of at most 200 characters.")] InvalidAvatarUri,
+    #[msg("Bio must be at most 160 bytes without control characters.")] InvalidBio,
+    #[msg("The name registry accounts for this name change were not passed.")] NameRegistryMissing,
+    #[msg("A name registry account was passed without a name change.")] UnexpectedNameRegistry,
+    #[msg("This achievement has not been unlocked.")] AchievementLocked,
+    #[msg("The badge for this achievement was already minted.")] BadgeAlreadyMinted,
+    #[msg("Level XP thresholds must be ascending, with unused levels set to 0 at the end.")] InvalidLevelThresholds,
+    #[msg("Stake exceeds the maximum for the user's level.")] StakeAboveLevelLimit,
+    #[msg("Receipt accounts were passed for a bet without a receipt NFT.")] UnexpectedReceiptAccounts,
+    #[msg("The user has not joined the current season; place a bet or call join_season first.")] SeasonNotJoined,
+    #[msg("This recurring bet iteration has not been settled by its subscription yet; crank or cancel the subscription first.")] RecurringIterationPending,
+    #[msg("The oracle still has a usable price for this bet; resolve it instead of voiding it.")] BetStillResolvable,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Size of a version 1 profile; BettingStats, season_id and season_net_pnl were all carved out of its reserved space
+    const USER_PROFILE_V1_SPACE: usize = DISCRIMINATOR_LENGTH + 32 + 8 + 1 + 8 + 1 + 64;
+
+    #[test]
+    fn user_profile_layout_only_grows_at_the_end() {
+        let profile = UserProfile { season_net_pnl: -1, last_refill_at: -1, ..Default::default() };
+        let mut data = Vec::new();
+        profile.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), USER_PROFILE_SPACE);
+        assert_eq!(DISCRIMINATOR_LENGTH + 32 + 8 + 1 + 8 + 1 + BETTING_STATS_SIZE + 8 + 8, USER_PROFILE_V1_SPACE);
+        // The last version 1 field ends exactly where a version 1 account ended, and version 2 starts right after it
+        assert_eq!(data[USER_PROFILE_V1_SPACE - 8..USER_PROFILE_V1_SPACE], [0xff; 8]);
+        assert_eq!(data[USER_PROFILE_V1_SPACE..USER_PROFILE_V1_SPACE + 8], [0xff; 8]);
+    }
+
+    fn user(seed: u8) -> Pubkey {
+        Pubkey::new_from_array([seed; 32])
+    }
+
+    fn ranked_users(leaderboard: &Leaderboard) -> Vec<Pubkey> {
+        leaderboard.entries.iter().map(|entry| entry.user).collect()
+    }
+
+    #[test]
+    fn leaderboard_ranks_by_score_then_time_then_pubkey() {
+        let mut leaderboard = Leaderboard::default();
+        assert!(leaderboard.submit(user(1), 100, 50));
+        assert!(leaderboard.submit(user(2), 300, 50));
+        assert!(leaderboard.submit(user(3), 100, 40)); // Reached 100 first
+        assert!(leaderboard.submit(user(0), 100, 50)); // Same score and time as user 1, lower pubkey
+        assert_eq!(ranked_users(&leaderboard), vec![user(2), user(3), user(0), user(1)]);
+    }
+
+    #[test]
+    fn leaderboard_resubmits_move_existing_entries() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.submit(user(1), 100, 1);
+        leaderboard.submit(user(2), 200, 1);
+        assert!(!leaderboard.submit(user(1), 100, 5)); // Unchanged score keeps the original timestamp
+        assert_eq!(leaderboard.entries[1].updated_at, 1);
+        assert!(leaderboard.submit(user(1), 300, 6));
+        assert_eq!(ranked_users(&leaderboard), vec![user(1), user(2)]);
+        assert!(leaderboard.submit(user(1), 50, 7)); // Scores can drop too
+        assert_eq!(ranked_users(&leaderboard), vec![user(2), user(1)]);
+        assert_eq!(leaderboard.entries.len(), 2);
+    }
+
+    #[test]
+    fn full_leaderboard_only_admits_users_above_the_cutoff() {
+        let mut leaderboard = Leaderboard::default();
+        for seed in 0..LEADERBOARD_CAPACITY as u8 {
+            leaderboard.submit(user(seed + 1), 100 + seed as i64, 10);
+        }
+        let cutoff = *leaderboard.entries.last().unwrap();
+        assert_eq!(cutoff.score, 100);
+        // Ties with the cutoff only get in by reaching the score earlier
+        assert!(!leaderboard.submit(user(200), 100, 10));
+        assert!(!leaderboard.submit(user(201), 99, 1));
+        assert!(leaderboard.submit(user(202), 100, 9));
+        assert_eq!(leaderboard.entries.len(), LEADERBOARD_CAPACITY);
+        assert!(!ranked_users(&leaderboard).contains(&cutoff.user));
+        assert_eq!(leaderboard.entries.last().unwrap().user, user(202));
+    }
+
+    #[test]
+    fn leaderboard_rerank_skips_profiles_from_other_seasons() {
+        let mut leaderboard = Leaderboard { metric: LeaderboardMetric::NetPnl, season_id: 2, ..Default::default() };
+        let current = UserProfile { authority: user(1), season_id: 2, season_net_pnl: -5, ..Default::default() };
+        let stale = UserProfile { authority: user(2), season_id: 1, season_net_pnl: 500, ..Default::default() };
+        leaderboard.rerank(&[&current, &stale], 1);
+        assert_eq!(ranked_users(&leaderboard), vec![user(1)]);
+        assert_eq!(leaderboard.entries[0].score, -5);
+    }
+
+    fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b { hashv(&[&[1u8], &a, &b]).to_bytes() } else { hashv(&[&[1u8], &b, &a]).to_bytes() }
+    }
+
+    // Three-claim tree: root = H(H(leaf0, leaf1), leaf2)
+    fn reward_tree() -> ([[u8; 32]; 3], [u8; 32]) {
+        let leaves = [
+            season_reward_leaf(0, &user(1), 500),
+            season_reward_leaf(1, &user(2), 300),
+            season_reward_leaf(2, &user(3), 100),
+        ];
+        let root = hash_pair(hash_pair(leaves[0], leaves[1]), leaves[2]);
+        (leaves, root)
+    }
+
+    #[test]
+    fn merkle_proofs_verify_every_claim() {
+        let (leaves, root) = reward_tree();
+        assert!(verify_merkle_proof(&[leaves[1], leaves[2]], &root, leaves[0]));
+        assert!(verify_merkle_proof(&[leaves[0], leaves[2]], &root, leaves[1]));
+        assert!(verify_merkle_proof(&[hash_pair(leaves[0], leaves[1])], &root, leaves[2]));
+    }
+
+    #[test]
+    fn merkle_proofs_reject_altered_claims() {
+        let (leaves, root) = reward_tree();
+        let proof = [leaves[1], leaves[2]];
+        assert!(!verify_merkle_proof(&proof, &root, season_reward_leaf(0, &user(1), 501)));
+        assert!(!verify_merkle_proof(&proof, &root, season_reward_leaf(3, &user(1), 500)));
+        assert!(!verify_merkle_proof(&proof, &root, season_reward_leaf(0, &user(9), 500)));
+        assert!(!verify_merkle_proof(&proof[..1], &root, leaves[0]));
+        assert!(!verify_merkle_proof(&[], &root, leaves[0]));
+    }
+
+    #[test]
+    fn reward_leaves_are_domain_separated() {
+        let claimant = user(1);
+        let fields: [&[u8]; 3] = [&0u32.to_le_bytes(), claimant.as_ref(), &500u64.to_le_bytes()];
+        let leaf = season_reward_leaf(0, &user(1), 500);
+        assert_eq!(leaf, hashv(&[&[0u8], fields[0], fields[1], fields[2]]).to_bytes());
+        assert_ne!(leaf, hashv(&fields).to_bytes());
+        assert_ne!(leaf, hashv(&[&[1u8], fields[0], fields[1], fields[2]]).to_bytes()); // Inner-node prefix
+    }
+
+    fn idempotency_key(n: u64) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&n.to_le_bytes());
+        key
+    }
+
+    #[test]
+    fn idempotency_log_remembers_keys_until_full() {
+        let mut log = IdempotencyLog::default();
+        for n in 0..IDEMPOTENCY_LOG_CAPACITY as u64 {
+            log.record(idempotency_key(n), n);
+        }
+        assert_eq!(log.entries.len(), IDEMPOTENCY_LOG_CAPACITY);
+        assert_eq!(log.next_slot, 0);
+        for n in 0..IDEMPOTENCY_LOG_CAPACITY as u64 {
+            assert_eq!(log.find(&idempotency_key(n)), Some(n));
+        }
+        assert_eq!(log.find(&idempotency_key(IDEMPOTENCY_LOG_CAPACITY as u64)), None);
+    }
+
+    #[test]
+    fn idempotency_log_overwrites_the_oldest_key_when_full() {
+        let mut log = IdempotencyLog::default();
+        let total = 2 * IDEMPOTENCY_LOG_CAPACITY as u64 + 3;
+        for n in 0..total {
+            log.record(idempotency_key(n), n);
+        }
+        assert_eq!(log.entries.len(), IDEMPOTENCY_LOG_CAPACITY);
+        assert_eq!(log.next_slot, 3);
+        let oldest_kept = total - IDEMPOTENCY_LOG_CAPACITY as u64;
+        assert_eq!(log.find(&idempotency_key(oldest_kept - 1)), None);
+        for n in oldest_kept..total {
+            assert_eq!(log.find(&idempotency_key(n)), Some(n));
+        }
+    }
+
+    #[test]
+    fn slippage_check_is_disabled_without_an_expected_price() {
+        assert!(check_price_slippage(u64::MAX, None, 0).is_ok());
+        // Zero is an ordinary expected price, not a way to turn the check off
+        assert!(check_price_slippage(1, Some(0), u16::MAX).is_err());
+    }
+
+    #[test]
+    fn slippage_check_accepts_moves_up_to_the_limit() {
+        // 1% of 1_000_000 is 10_000
+        assert!(check_price_slippage(1_010_000, Some(1_000_000), 100).is_ok());
+        assert!(check_price_slippage(990_000, Some(1_000_000), 100).is_ok());
+        assert!(check_price_slippage(1_000_000, Some(1_000_000), 0).is_ok());
+    }
+
+    #[test]
+    fn slippage_check_rejects_moves_past_the_limit() {
+        assert!(check_price_slippage(1_010_001, Some(1_000_000), 100).is_err());
+        assert!(check_price_slippage(989_999, Some(1_000_000), 100).is_err());
+        assert!(check_price_slippage(1_000_001, Some(1_000_000), 0).is_err());
+    }
+
+    #[test]
+    fn slippage_check_does_not_overflow_on_extreme_prices() {
+        assert!(check_price_slippage(u64::MAX, Some(u64::MAX), u16::MAX).is_ok());
+        assert!(check_price_slippage(0, Some(u64::MAX), u16::MAX).is_ok()); // u16::MAX bps allows a move above 100%
+        assert!(check_price_slippage(0, Some(u64::MAX), BPS_DENOMINATOR as u16 - 1).is_err());
+    }
+
+    fn serialized<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn version_1_profile() -> UserProfile {
+        UserProfile {
+            authority: user(7),
+            points: 640,
+            bet_count: 12,
+            version: 1,
+            stats: BettingStats { bets_placed: 12, bets_won: 5, net_pnl: -360, ..Default::default() },
+            season_net_pnl: -360,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn short_accounts_deserialize_with_zeroed_new_fields() {
+        let mut data = serialized(&version_1_profile());
+        data.truncate(USER_PROFILE_V1_SPACE);
+        assert!(UserProfile::try_deserialize(&mut data.as_slice()).is_err());
+
+        let profile = deserialize_padded::<UserProfile>(&data).unwrap();
+        assert_eq!(profile.version, 1);
+        assert_eq!((profile.authority, profile.points, profile.bet_count), (user(7), 640, 12));
+        assert_eq!((profile.stats.bets_placed, profile.stats.bets_won, profile.stats.net_pnl), (12, 5, -360));
+        assert_eq!(profile.season_net_pnl, -360);
+        assert_eq!((profile.last_refill_at, profile.referrer, profile.xp), (0, Pubkey::default(), 0));
+    }
+
+    // Full-size accounts are upgraded without a realloc, so this runs without the Rent sysvar
+    fn upgrade_in_place<T: VersionedAccount>(data: &mut [u8]) -> Result<u8> {
+        let (key, owner) = (user(1), crate::ID);
+        let mut lamports = 0;
+        let account_info = AccountInfo::new(&key, false, true, &mut lamports, data, &owner, false, 0);
+        upgrade_account_layout::<T>(&account_info, &account_info, &account_info)
+    }
+
+    #[test]
+    fn upgrade_rewrites_the_version_and_keeps_the_fields() {
+        let mut data = serialized(&version_1_profile());
+        assert_eq!(upgrade_in_place::<UserProfile>(&mut data).unwrap(), 1);
+        let profile = UserProfile::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(profile.version, USER_PROFILE_VERSION);
+        assert_eq!((profile.points, profile.stats.bets_won), (640, 5));
+
+        // Upgrading again is a no-op
+        let upgraded = data.clone();
+        assert_eq!(upgrade_in_place::<UserProfile>(&mut data).unwrap(), USER_PROFILE_VERSION);
+        assert_eq!(data, upgraded);
+    }
+
+    #[test]
+    fn upgrading_a_version_0_bet_makes_the_bettor_its_rent_payer() {
+        let bettor = user(7);
+        let mut data = serialized(&ActiveBet { user: bettor, version: 0, ..Default::default() });
+        data.resize(ACTIVE_BET_SPACE, 0); // Bets are allocated for the longest asset name
+        assert_eq!(upgrade_in_place::<ActiveBet>(&mut data).unwrap(), 0);
+        let bet = ActiveBet::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(bet.rent_payer, bettor);
+        assert_eq!(bet.version, ACTIVE_BET_VERSION);
+    }
+
+    #[test]
+    fn upgrading_a_version_0_config_fills_in_the_market_round_limits() {
+        let mut data = serialized(&ProgramConfig { cancel_window_seconds: 45, ..Default::default() });
+        data.truncate(data.len() - 1 - 32 - 8 - 8); // Version 0 ended before the market round limits
+        data.resize(PROGRAM_CONFIG_SPACE, 0);
+        assert_eq!(upgrade_in_place::<ProgramConfig>(&mut data).unwrap(), 0);
+        let config = ProgramConfig::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(config.version, PROGRAM_CONFIG_VERSION);
+        assert_eq!(config.cancel_window_seconds, 45);
+        assert_eq!(config.market_round_staking_seconds, DEFAULT_MARKET_ROUND_STAKING_SECONDS);
+        assert_eq!(config.max_market_round_duration_seconds, DEFAULT_MAX_MARKET_ROUND_DURATION_SECONDS);
+    }
+
+    #[test]
+    fn versioned_accounts_fit_their_space_with_the_longest_strings() {
+        let asset_name = "A".repeat(MAX_ASSET_NAME_LENGTH);
+        assert!(serialized(&ActiveBet { asset_name: asset_name.clone(), ..Default::default() }).len() <= ACTIVE_BET_SPACE);
+        assert!(serialized(&MarketRound { asset_name: asset_name.clone(), ..Default::default() }).len() <= MARKET_ROUND_SPACE);
+        assert!(serialized(&LimitOrder { asset_name: asset_name.clone(), ..Default::default() }).len() <= LIMIT_ORDER_SPACE);
+        assert!(serialized(&ScheduledBet { asset_name: asset_name.clone(), ..Default::default() }).len() <= SCHEDULED_BET_SPACE);
+        assert!(serialized(&RecurringBet { asset_name, ..Default::default() }).len() <= RECURRING_BET_SPACE);
+        let entries = vec![IdempotencyEntry::default(); IDEMPOTENCY_LOG_CAPACITY];
+        assert!(serialized(&IdempotencyLog { entries, ..Default::default() }).len() <= IDEMPOTENCY_LOG_SPACE);
+        assert_eq!(serialized(&ProgramConfig::default()).len(), PROGRAM_CONFIG_SPACE);
+        assert_eq!(serialized(&ReceiptListing::default()).len(), RECEIPT_LISTING_SPACE);
+    }
+
+    #[test]
+    fn upgrade_rejects_versions_from_a_newer_program() {
+        let mut data = serialized(&UserProfile { version: USER_PROFILE_VERSION + 1, ..Default::default() });
+        assert!(upgrade_in_place::<UserProfile>(&mut data).is_err());
+    }
+
+    #[test]
+    fn bets_on_an_unchanged_price_are_pushed_and_refunded() {
+        for direction in [Direction::Up, Direction::Down] {
+            let mut bet = ActiveBet { initial_price: 1_000_000, amount_staked: 50, direction, ..Default::default() };
+            assert_eq!(bet.settle(1_000_000).unwrap(), 50);
+            assert_eq!(bet.status, BetStatus::Push);
+        }
+        let mut bet = ActiveBet { initial_price: 1_000_000, amount_staked: 50, direction: Direction::Up, ..Default::default() };
+        assert_eq!(bet.settle(1_000_001).unwrap(), 100);
+        assert_eq!(bet.status, BetStatus::Won);
+    }
+
+    #[test]
+    fn joining_a_new_season_returns_the_previous_balance() {
+        let season_config = SeasonConfig { current_season_id: 2, starting_points: 1_000, ..Default::default() };
+        let mut profile = UserProfile { points: 1_640, season_id: 1, season_net_pnl: 640, ..Default::default() };
+        assert_eq!(profile.sync_season(&season_config), Some(1_640));
+        assert_eq!((profile.points, profile.season_id, profile.season_net_pnl), (1_000, 2, 0));
+        assert_eq!(profile.sync_season(&season_config), None);
+    }
+
+    #[test]
+    fn push_feed_address_is_the_sponsored_sol_usd_account() {
+        assert_eq!(sol_usd_push_feed_address().unwrap(), pubkey!("7UVimffxr9ow1uXYxsr4LHAcV58mLzhmwaeKvJ1pjLiE"));
+    }
+
+    #[test]
+    fn reserved_points_are_only_released_in_their_season() {
+        let mut profile = UserProfile { points: 500, season_id: 1, ..Default::default() };
+        profile.reserve_points(300).unwrap();
+        assert_eq!((profile.points, profile.reserved_points), (200, 300));
+        assert!(profile.reserve_points(201).is_err());
+        profile.release_reserved_points(1, 100);
+        assert_eq!(profile.reserved_points, 200);
+
+        profile.sync_season(&SeasonConfig { current_season_id: 2, starting_points: 1_000, ..Default::default() });
+        assert_eq!(profile.reserved_points, 0);
+        profile.reserve_points(50).unwrap();
+        profile.release_reserved_points(1, 200); // An order from season 1 does not release season 2 reserves
+        assert_eq!(profile.reserved_points, 50);
+    }
+
+    #[test]
+    fn betting_stats_size_matches_serialization() {
+        let mut data = Vec::new();
+        BettingStats::default().serialize(&mut data).unwrap();
+        assert_eq!(data.len(), BETTING_STATS_SIZE);
+    }
+}
@@ -20,6 +20,16 @@ describe("bs_bet_on_devnet", () => {
   let userProfilePda: PublicKey;
   let userAuthStatePda: PublicKey;
 
+  // Bets are PDAs derived from the profile's bet counter: [b"bet", user, bet_index (u64 LE)]
+  const nextBetPda = async (): Promise<[PublicKey, BN]> => {
+    const profile = await program.account.userProfile.fetch(userProfilePda);
+    const [betPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("bet"), user.publicKey.toBuffer(), profile.betCount.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+    return [betPda, profile.betCount];
+  };
+
   before(async () => {
     testUserKeypair = Keypair.generate();
     user = new anchor.Wallet(testUserKeypair); // Create a usable Wallet object
@@ -52,6 +62,26 @@ describe("bs_bet_on_devnet", () => {
       [Buffer.from("auth_state"), user.publicKey.toBuffer()], // Use testUser's publicKey
       program.programId
     );
+    // open_bet and the other staking instructions read level caps from the program config
+    const [configPda] = PublicKey.findProgramAddressSync([Buffer.from("config")], program.programId);
+    if ((await provider.connection.getAccountInfo(configPda)) === null) {
+      // Only the upgrade authority (the deploying wallet) may create the config
+      const [programDataPda] = PublicKey.findProgramAddressSync(
+        [program.programId.toBuffer()],
+        new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+      );
+      await program.methods.initializeConfig().accounts({ admin: provider.wallet.publicKey, programData: programDataPda }).rpc();
+      console.log(`Program config initialized: ${configPda.toBase58()}`);
+    }
+    // Every instruction that settles a bet re-ranks the touched profiles on both leaderboards
+    for (const [metric, seed] of [[{ points: {} }, 0], [{ netPnl: {} }, 1]] as const) {
+      const [leaderboardPda] = PublicKey.findProgramAddressSync([Buffer.from("leaderboard"), Buffer.from([seed])], program.programId);
+      if ((await provider.connection.getAccountInfo(leaderboardPda)) === null) {
+        await program.methods.initializeLeaderboard(metric as any).accounts({ admin: provider.wallet.publicKey } as any).rpc();
+        console.log(`Leaderboard ${seed} initialized: ${leaderboardPda.toBase58()}`);
+      }
+    }
+
     console.log(`UserProfile PDA for this run: ${userProfilePda.toBase58()}`);
     console.log(`UserAuthState PDA for this run: ${userAuthStatePda.toBase58()}`);
     console.log("--- 'before all' setup complete ---");
@@ -69,6 +99,7 @@ describe("bs_bet_on_devnet", () => {
         userAuthStateForProfileCreation: userAuthStatePda,
         userAuthority: user.publicKey, // testUserKeypair.publicKey
         systemProgram: SystemProgram.programId,
+        referrerProfile: null, seasonConfig: null, // No referrer
       } as any)
       .signers([testUserKeypair]) // The testUserKeypair must sign if it's the authority
       .rpc({ commitment: "confirmed" });
@@ -81,41 +112,51 @@ describe("bs_bet_on_devnet", () => {
 
   const betAmount = new BN(10);
   const betDuration = new BN(60);
-  let firstBetAccountKp = Keypair.generate();
 
   it("2. Places a Standard Bet (UserAuthState is_delegated=false)", async () => {
     console.log("--- Test 2: First Standard Bet ---");
+    const [firstBetPda, firstBetIndex] = await nextBetPda();
     await program.methods
-      .openBet("SOL/USD", 1, betAmount, betDuration, user.publicKey) // user_authority_for_pdas is testUser
+      .openBet("SOL/USD", { up: {} }, betAmount, betDuration, user.publicKey, firstBetIndex, null, 0, null) // user_authority_for_pdas is testUser; no expected price skips the slippage check
       .accounts({
-        betAccount: firstBetAccountKp.publicKey,
+        betAccount: firstBetPda,
         userSigner: user.publicKey, // testUserKeypair.publicKey
         userAuthState: userAuthStatePda,
         userProfile: userProfilePda,
         pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
         systemProgram: SystemProgram.programId,
+        idempotencyLog: null, // No idempotency key
+        seasonRecord: null, // Seasons not started
+        receiptMint: null, receiptTokenAccount: null, tokenProgram: null, associatedTokenProgram: null, // No receipt NFT
       } as any)
-      .signers([testUserKeypair, firstBetAccountKp]) // testUserKeypair is the userSigner
+      .signers([testUserKeypair]) // testUserKeypair is the userSigner
       .rpc({ commitment: "confirmed" });
 
+    const bet = await program.account.activeBet.fetch(firstBetPda);
+    expect(bet.betIndex.toNumber()).to.equal(0);
+    const profile = await program.account.userProfile.fetch(userProfilePda);
+    expect(profile.betCount.toNumber()).to.equal(1);
     const authState = await program.account.userAuthState.fetch(userAuthStatePda);
     expect(authState.isDelegated).to.be.false;
   });
 
   it("3. Places a Second Standard Bet (UserAuthState is_delegated=false)", async () => {
     console.log("--- Test 3: Second Standard Bet ---");
-    let secondBetAccountKp = Keypair.generate();
+    const [secondBetPda, secondBetIndex] = await nextBetPda();
     await program.methods
-      .openBet("SOL/USD", 0, betAmount, betDuration, user.publicKey)
+      .openBet("SOL/USD", { down: {} }, betAmount, betDuration, user.publicKey, secondBetIndex, null, 0, null)
       .accounts({
-        betAccount: secondBetAccountKp.publicKey,
+        betAccount: secondBetPda,
         userSigner: user.publicKey,
         userAuthState: userAuthStatePda,
         userProfile: userProfilePda,
         pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
         systemProgram: SystemProgram.programId,
+        idempotencyLog: null, // No idempotency key
+        seasonRecord: null, // Seasons not started
+        receiptMint: null, receiptTokenAccount: null, tokenProgram: null, associatedTokenProgram: null, // No receipt NFT
       } as any)
-      .signers([testUserKeypair, secondBetAccountKp])
+      .signers([testUserKeypair])
       .rpc({ commitment: "confirmed" });
 
     const authState = await program.account.userAuthState.fetch(userAuthStatePda);
@@ -159,19 +200,22 @@ describe("bs_bet_on_devnet", () => {
 
   it("5. Places a Bet with Quick Bets ON (is_delegated=true) - EXPECTED TO FAIL OR BEHAVE DIFFERENTLY", async () => {
     console.log("--- Test 5: Bet with Quick Bets ON ---");
-    let thirdBetAccountKp = Keypair.generate();
+    const [thirdBetPda, thirdBetIndex] = await nextBetPda();
     try {
       await program.methods
-        .openBet("SOL/USD", 1, betAmount, betDuration, user.publicKey)
+        .openBet("SOL/USD", { up: {} }, betAmount, betDuration, user.publicKey, thirdBetIndex, null, 0, null)
         .accounts({
-          betAccount: thirdBetAccountKp.publicKey,
+          betAccount: thirdBetPda,
           userSigner: user.publicKey,
           userAuthState: userAuthStatePda, // This account is now owned by MagicBlock
           userProfile: userProfilePda,
           pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
           systemProgram: SystemProgram.programId,
+          idempotencyLog: null, // No idempotency key
+          seasonRecord: null, // Seasons not started
+          receiptMint: null, receiptTokenAccount: null, tokenProgram: null, associatedTokenProgram: null, // No receipt NFT
         } as any)
-        .signers([testUserKeypair, thirdBetAccountKp])
+        .signers([testUserKeypair])
         .rpc({ commitment: "confirmed" });
 
       // If it reaches here, it means MagicBlock's ephemeral system allowed it
@@ -194,4 +238,117 @@ describe("bs_bet_on_devnet", () => {
       throw error; // Re-throw other unexpected errors
     }
   });
-});
\ No newline at end of file
+});
+
+// Keeper paths anyone can call. Uses its own bettor so the delegation in the suite above doesn't interfere.
+describe("bs_bet_permissionless_paths", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+  const program = anchor.workspace.BsBet as Program<BsBet>;
+
+  const bettor = Keypair.generate();
+  const keeper = Keypair.generate();
+  const pda = (...seeds: Buffer[]) => PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+  const bettorProfilePda = pda(Buffer.from("profile"), bettor.publicKey.toBuffer());
+  const bettorAuthStatePda = pda(Buffer.from("auth_state"), bettor.publicKey.toBuffer());
+  const betPda = pda(Buffer.from("bet"), bettor.publicKey.toBuffer(), new BN(0).toArrayLike(Buffer, "le", 8));
+
+  const expectBetError = async (call: Promise<unknown>, code: string) => {
+    try {
+      await call;
+      expect.fail(`Expected ${code}`);
+    } catch (error) {
+      if (!(error instanceof anchor.AnchorError)) throw error;
+      expect(error.error.errorCode.code).to.equal(code);
+    }
+  };
+
+  before(async () => {
+    // Fund both wallets from the provider instead of relying on devnet airdrops
+    const fundTx = new web3.Transaction().add(
+      SystemProgram.transfer({ fromPubkey: provider.wallet.publicKey, toPubkey: bettor.publicKey, lamports: LAMPORTS_PER_SOL / 10 }),
+      SystemProgram.transfer({ fromPubkey: provider.wallet.publicKey, toPubkey: keeper.publicKey, lamports: LAMPORTS_PER_SOL / 100 }),
+    );
+    await provider.sendAndConfirm(fundTx, [], { commitment: "confirmed" });
+
+    await program.methods
+      .openBet("SOL/USD", { up: {} }, new BN(10), new BN(60), bettor.publicKey, new BN(0), null, 0, null)
+      .accounts({
+        betAccount: betPda,
+        userSigner: bettor.publicKey,
+        userAuthState: bettorAuthStatePda,
+        userProfile: bettorProfilePda,
+        pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
+        systemProgram: SystemProgram.programId,
+        idempotencyLog: null, // No idempotency key
+        seasonRecord: null, // Seasons not started
+        receiptMint: null, receiptTokenAccount: null, tokenProgram: null, associatedTokenProgram: null, // No receipt NFT
+      } as any)
+      .signers([bettor])
+      .rpc({ commitment: "confirmed" });
+  });
+
+  it("Rejects a third-party resolve_bet before the bet expires", async () => {
+    await expectBetError(
+      program.methods
+        .resolveBet()
+        .accounts({
+          betAccount: betPda,
+          resolverSigner: keeper.publicKey,
+          userAuthState: bettorAuthStatePda,
+          userProfile: bettorProfilePda,
+          pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          systemProgram: SystemProgram.programId,
+          receiptTokenAccount: null, receiptHolderProfile: null, // No receipt NFT
+          resolverProfile: null, // Keeper has no profile: no tip
+        } as any)
+        .signers([keeper])
+        .rpc({ commitment: "confirmed" }),
+      "BetNotYetExpired"
+    );
+    const bet = await program.account.activeBet.fetch(betPda);
+    expect(bet.status).to.deep.equal({ active: {} });
+  });
+
+  it("Skips unexpired bets in resolve_bets without settling them", async () => {
+    const profileBefore = await program.account.userProfile.fetch(bettorProfilePda);
+    await program.methods
+      .resolveBets()
+      .accounts({
+        resolverSigner: keeper.publicKey,
+        pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
+        resolverProfile: null, // Keeper has no profile: no tip
+      } as any)
+      .remainingAccounts([
+        { pubkey: betPda, isSigner: false, isWritable: true },
+        { pubkey: bettorProfilePda, isSigner: false, isWritable: true },
+      ])
+      .signers([keeper])
+      .rpc({ commitment: "confirmed" });
+
+    const bet = await program.account.activeBet.fetch(betPda);
+    expect(bet.status).to.deep.equal({ active: {} });
+    const profileAfter = await program.account.userProfile.fetch(bettorProfilePda);
+    expect(profileAfter.points.toNumber()).to.equal(profileBefore.points.toNumber());
+  });
+
+  it("Rejects void_bet before the grace period after expiry", async () => {
+    await expectBetError(
+      program.methods
+        .voidBet()
+        .accounts({
+          betAccount: betPda,
+          userProfile: bettorProfilePda,
+          voider: keeper.publicKey,
+          pythPriceFeed: PYTH_SOL_USD_PRICE_ACCOUNT,
+          receiptTokenAccount: null, receiptHolderProfile: null, // No receipt NFT
+        } as any)
+        .signers([keeper])
+        .rpc({ commitment: "confirmed" }),
+      "VoidGracePeriodNotElapsed"
+    );
+    const bet = await program.account.activeBet.fetch(betPda);
+    expect(bet.status).to.deep.equal({ active: {} });
+  });
+});